@@ -1,37 +1,196 @@
+use crate::events::Events;
 use crate::http_bridge::HttpBridgeApi;
+use crate::storage::Pipeline;
+use crate::types::SyncState;
 use crate::Pool;
 use cardano::block::types::HeaderHash;
+use cardano::config::ProtocolMagic;
 use r2d2_sqlite::SqliteConnectionManager;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The chain to follow, which sets the genesis the import starts from.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Network {
+    Mainnet,
+    Staging,
+    Testnet,
+    // a network described by the genesis json at this path
+    Custom(PathBuf),
+}
+
+impl Network {
+    /// The network named in the settings. Other networks than the known ones
+    /// need their genesis file, which the known ones can't be given.
+    pub fn new(name: &str, genesis_file: Option<String>) -> Result<Self, String> {
+        match (name, genesis_file) {
+            ("mainnet", None) => Ok(Network::Mainnet),
+            ("staging", None) => Ok(Network::Staging),
+            ("testnet", None) => Ok(Network::Testnet),
+            ("mainnet", Some(_)) | ("staging", Some(_)) | ("testnet", Some(_)) => Err(format!(
+                "The genesis of {} is built in, genesis-file is only for other networks",
+                name
+            )),
+            (_, Some(path)) => Ok(Network::Custom(PathBuf::from(path))),
+            (other, None) => Err(format!(
+                "Unknown network {}, expected mainnet, staging, testnet or a genesis-file",
+                other
+            )),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Config<T: HttpBridgeApi> {
     pub genesis_prev: HeaderHash,
-    pub genesis: HeaderHash,
+    // the first block, only known for the predefined networks
+    pub genesis: Option<HeaderHash>,
+    // the genesis json, with the initial utxos
+    pub genesis_data: String,
+    // what the witnesses of the network's transactions sign along with them
+    pub protocol_magic: ProtocolMagic,
     pub pool: Pool,
     pub port: u16,
     pub bridge: T,
     pub epoch_stability_depth: usize,
     pub refresh_interval: u64,
+    // blocks and milliseconds after which the sync loop commits
+    pub sync_batch_size: usize,
+    pub sync_batch_interval: u64,
+    pub sync_state: Arc<Mutex<SyncState>>,
+    // what the sync loop applies, for the address streams
+    pub events: Events,
+    pub stream_max_clients: usize,
+    // required by the admin api, which is disabled without it
+    pub admin_token: Option<String>,
+    // seconds after which a submitted transaction not in a block is expired
+    pub pending_timeout: u64,
+    // seconds after which an expired submission is forgotten
+    pub expired_retention: u64,
+    // how `sync-block-index` fetches and decodes the stable epochs
+    pub import_pipeline: Pipeline,
+}
+
+fn predefined(cfg: exe_common::config::net::Config) -> (Option<HeaderHash>, String) {
+    let genesis_data = exe_common::genesisdata::data::get_genesis_data(&cfg.genesis_prev)
+        .expect("genesis data of a predefined network");
+
+    (Some(cfg.genesis), genesis_data.to_string())
 }
 
 impl<T: HttpBridgeApi> Config<T> {
     pub fn new(
+        network: Network,
         port: u16,
         bridge: T,
         connection_manager: SqliteConnectionManager,
         refresh_interval: u64,
-    ) -> Self {
-        let cfg = exe_common::config::net::Config::mainnet();
-        let pool = r2d2::Pool::new(connection_manager).unwrap();
+        sync_batch_size: usize,
+        sync_batch_interval: u64,
+        stream_max_clients: usize,
+        admin_token: Option<String>,
+        pending_timeout: u64,
+        expired_retention: u64,
+        import_pipeline: Pipeline,
+    ) -> Result<Self, String> {
+        let (genesis, genesis_data) = match network {
+            Network::Mainnet => predefined(exe_common::config::net::Config::mainnet()),
+            Network::Staging => predefined(exe_common::config::net::Config::staging()),
+            Network::Testnet => predefined(exe_common::config::net::Config::testnet()),
+            Network::Custom(path) => {
+                let data = fs::read_to_string(&path).map_err(|e| {
+                    format!("Could not read genesis file {}: {}", path.display(), e)
+                })?;
+                (None, data)
+            }
+        };
 
-        Config {
-            genesis_prev: cfg.genesis_prev,
+        let parsed = exe_common::genesisdata::parse::parse(genesis_data.as_bytes());
+        let pool = r2d2::Pool::new(connection_manager)
+            .map_err(|e| format!("Could not open the database: {}", e))?;
+
+        Ok(Config {
+            genesis_prev: parsed.genesis_prev,
             pool,
             port,
             bridge,
             refresh_interval,
-            genesis: cfg.genesis,
-            epoch_stability_depth: cfg.epoch_stability_depth,
+            sync_batch_size,
+            sync_batch_interval,
+            genesis,
+            genesis_data,
+            protocol_magic: parsed.protocol_magic,
+            epoch_stability_depth: parsed.epoch_stability_depth,
+            sync_state: Arc::new(Mutex::new(SyncState::default())),
+            events: Events::new(stream_max_clients),
+            stream_max_clients,
+            admin_token,
+            pending_timeout,
+            expired_retention,
+            import_pipeline,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_bridge::{Bridge, HttpBridge};
+    use std::path::Path;
+
+    fn config(network: Network) -> Result<Config<Bridge>, String> {
+        Config::new(
+            network,
+            0,
+            Bridge::Http(HttpBridge::new("http://127.0.0.1:1/mainnet/".to_string())),
+            SqliteConnectionManager::memory(),
+            0,
+            1000,
+            10000,
+            100,
+            None,
+            7200,
+            604800,
+            Pipeline::default(),
+        )
+    }
+
+    #[test]
+    fn test_network_selection() {
+        assert!(Network::new("mainnet", None) == Ok(Network::Mainnet));
+        assert!(Network::new("staging", None) == Ok(Network::Staging));
+        assert!(Network::new("testnet", None) == Ok(Network::Testnet));
+        assert!(
+            Network::new("qa", Some("/tmp/genesis.json".to_string()))
+                == Ok(Network::Custom(PathBuf::from("/tmp/genesis.json")))
+        );
+
+        // the genesis of the known networks can't be replaced
+        assert!(Network::new("testnet", Some("/tmp/genesis.json".to_string())).is_err());
+        assert!(Network::new("qa", None).is_err());
+    }
+
+    #[test]
+    fn test_genesis_of_network() {
+        let mainnet = config(Network::Mainnet).unwrap();
+        let testnet = config(Network::Testnet).unwrap();
+        assert!(mainnet.genesis.is_some());
+        assert!(testnet.genesis.is_some());
+        assert!(mainnet.genesis_prev != testnet.genesis_prev);
+        assert!(mainnet.genesis_data != testnet.genesis_data);
+
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/synthetic/genesis.json");
+        let custom = config(Network::Custom(path.clone())).unwrap();
+        let parsed = exe_common::genesisdata::parse::parse(&fs::read(&path).unwrap());
+        assert!(custom.genesis.is_none());
+        assert!(custom.genesis_prev == parsed.genesis_prev);
+        assert!(custom.epoch_stability_depth == parsed.epoch_stability_depth);
+
+        match config(Network::Custom(path.with_file_name("missing.json"))) {
+            Err(e) => assert!(e.contains("missing.json"), "{}", e),
+            Ok(_) => panic!("a missing genesis file was read"),
         }
     }
 }
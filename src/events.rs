@@ -0,0 +1,103 @@
+//! What the sync loop commits, fanned out to the open address streams.
+
+use crate::metrics;
+use crate::types::{BlockActivity, Rollback};
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Events a subscriber can fall behind by before it is dropped.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Block(BlockActivity),
+    Rollback(Rollback),
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    senders: Vec<(u64, SyncSender<Event>)>,
+}
+
+#[derive(Clone)]
+pub struct Events {
+    subscribers: Arc<Mutex<Subscribers>>,
+    max_subscribers: usize,
+}
+
+/// The events published since `Events::subscribe`. Dropping it unsubscribes.
+pub struct Subscription {
+    id: u64,
+    receiver: Receiver<Event>,
+    events: Events,
+}
+
+impl Subscription {
+    /// Wait for the next event. Fails with `Disconnected` once the subscriber
+    /// was dropped for falling behind.
+    pub fn next(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut subscribers = self.events.subscribers.lock().unwrap();
+        subscribers.senders.retain(|(id, _)| *id != self.id);
+        metrics::set(metrics::STREAM_CLIENTS, &[], subscribers.senders.len() as f64);
+    }
+}
+
+impl Events {
+    pub fn new(max_subscribers: usize) -> Self {
+        Events {
+            subscribers: Arc::new(Mutex::new(Subscribers::default())),
+            max_subscribers,
+        }
+    }
+
+    /// Receive the events published from now on, `None` if there are already
+    /// `max_subscribers` subscriptions.
+    pub fn subscribe(&self) -> Option<Subscription> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        if subscribers.senders.len() >= self.max_subscribers {
+            return None;
+        }
+
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_BUFFER);
+        subscribers.senders.push((id, sender));
+        metrics::set(metrics::STREAM_CLIENTS, &[], subscribers.senders.len() as f64);
+
+        Some(Subscription {
+            id,
+            receiver,
+            events: self.clone(),
+        })
+    }
+
+    /// Send `event` to every subscription. The ones too far behind to take it
+    /// are disconnected, their clients resume from the last event they got.
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        subscribers
+            .senders
+            .retain(|(_, sender)| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping a stream {} events behind", SUBSCRIBER_BUFFER);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+
+        metrics::set(metrics::STREAM_CLIENTS, &[], subscribers.senders.len() as f64);
+    }
+}
@@ -3,15 +3,214 @@ use cardano::block::block::{Block, BlockHeader};
 use cardano::block::date::BlockDate;
 use cardano::block::types::HeaderHash;
 
+use crate::local_bridge::LocalBridge;
+use crate::metrics;
 use crate::EpochId;
 
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum Error {
+    // the request could not be sent or its response read
+    ConnectionError(reqwest::Error),
+    // the bridge answered the url with a non 2xx status
+    StatusError(String, reqwest::StatusCode),
+    // the cbor of what is named could not be decoded
+    DecodeError(String, cbor_event::Error),
+    StorageError(std::io::Error),
+    // what the bridge can't do, such as submitting transactions to a storage
+    // directory
+    NotSupported(String),
+}
+
+impl Error {
+    /// Whether the request may succeed if sent again.
+    fn is_transient(&self) -> bool {
+        match *self {
+            Error::ConnectionError(_) => true,
+            Error::StatusError(_, status) => {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::DecodeError(..) | Error::StorageError(_) | Error::NotSupported(_) => false,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::ConnectionError(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::StorageError(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ConnectionError(ref err) => fmt::Display::fmt(err, f),
+            Error::StatusError(ref url, status) => write!(f, "{} answered {}", url, status),
+            Error::DecodeError(ref what, ref err) => {
+                write!(f, "Could not decode {}: {}", what, err)
+            }
+            Error::StorageError(ref err) => fmt::Display::fmt(err, f),
+            Error::NotSupported(ref what) => write!(f, "The bridge can't {}", what),
+        }
+    }
+}
+
+/// How the bridge is queried. Failed requests are retried `retries` times when
+/// the failure may be transient, waiting `backoff` before the first retry and
+/// twice as long before each of the next ones. Requests are spaced by at least
+/// `min_interval`.
+#[derive(Clone, Debug)]
+pub struct HttpBridgeOptions {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    pub min_interval: Duration,
+}
+
+impl Default for HttpBridgeOptions {
+    fn default() -> Self {
+        HttpBridgeOptions {
+            timeout: Duration::from_secs(30),
+            retries: 5,
+            backoff: Duration::from_millis(500),
+            min_interval: Duration::from_millis(0),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct HttpBridge {
     url: String,
+    client: reqwest::Client,
+    options: HttpBridgeOptions,
+    // shared by the clones, so the rate limit holds for all of them
+    last_request: Arc<Mutex<Option<Instant>>>,
 }
 
 impl HttpBridge {
-    pub fn first_unstable_epoch(&self, epoch_stability_depth: usize) -> std::result::Result<EpochId, Box<std::error::Error>> {
+    pub fn with_options(url: String, options: HttpBridgeOptions) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(options.timeout)
+            .build()
+            .expect("http client");
+
+        HttpBridge {
+            url,
+            client,
+            options,
+            last_request: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Wait for the next free slot. The slot is reserved under the lock, and
+    /// waited for once it is released, so clones keep sending while one of
+    /// them waits.
+    fn throttle(&self) {
+        let wait = {
+            let mut last_request = self.last_request.lock().unwrap();
+            let now = Instant::now();
+
+            let slot = match *last_request {
+                Some(last) if last + self.options.min_interval > now => {
+                    last + self.options.min_interval
+                }
+                _ => now,
+            };
+            *last_request = Some(slot);
+
+            slot - now
+        };
+
+        thread::sleep(wait);
+    }
+
+    /// GET `url`, or POST `body` to it as json if there is one.
+    fn send_once(&self, url: &str, body: Option<&str>) -> Result<Vec<u8>, Error> {
+        self.throttle();
+
+        let request = match body {
+            Some(body) => self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string()),
+            None => self.client.get(url),
+        };
+
+        let mut resp = request.send()?;
+        if !resp.status().is_success() {
+            return Err(Error::StatusError(url.to_string(), resp.status()));
+        }
+
+        let mut buf: Vec<u8> = vec![];
+        resp.copy_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Get `path` from the bridge, retrying on transient failures.
+    fn fetch(&self, route: &'static str, path: &str) -> Result<Vec<u8>, Error> {
+        self.send(route, path, None)
+    }
+
+    /// Request `path` from the bridge, POSTing `body` if there is one, and
+    /// retry on transient failures.
+    fn send(&self, route: &'static str, path: &str, body: Option<&str>) -> Result<Vec<u8>, Error> {
+        let url = format!("{}{}", self.url, path);
+
+        measure(route, || {
+            let mut delay = self.options.backoff;
+            let mut attempt = 0;
+
+            loop {
+                match self.send_once(&url, body) {
+                    Err(ref e) if e.is_transient() && attempt < self.options.retries => {
+                        warn!(
+                            "Request to {} failed, retrying in {}ms: {}",
+                            url,
+                            delay.as_millis(),
+                            e
+                        );
+                        thread::sleep(delay);
+                        delay *= 2;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+}
+
+use std::result::Result;
+pub trait HttpBridgeApi {
+    fn new(url: String) -> Self;
+
+    fn get_tip(&self) -> Result<BlockHeader, Error>;
+
+    /// The block as the bridge serves it, before decoding, so its
+    /// transactions can be stored as they were signed.
+    fn get_raw_block(&self, blockid: &HeaderHash) -> Result<block::RawBlock, Error>;
+
+    fn get_block(&self, blockid: &HeaderHash) -> Result<Block, Error> {
+        decode_block(blockid, &self.get_raw_block(blockid)?)
+    }
+
+    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, Error>;
+
+    /// Submit the cbor of a signed `TxAux` to the network.
+    fn send_transaction(&self, raw: &[u8]) -> Result<(), Error>;
+
+    fn first_unstable_epoch(&self, epoch_stability_depth: usize) -> Result<EpochId, Error> {
         let tip = self.get_tip()?;
         let date = tip.get_blockdate();
         let first_unstable_epoch = date.get_epochid()
@@ -29,52 +228,203 @@ impl HttpBridge {
     }
 }
 
-use std::result::Result;
-pub trait HttpBridgeApi {
-    fn new(url: String) -> Self;
-
-    fn get_tip(&self) -> Result<BlockHeader, reqwest::Error>;
+pub fn decode_block(blockid: &HeaderHash, raw: &block::RawBlock) -> Result<Block, Error> {
+    raw.decode()
+        .map_err(|e| Error::DecodeError(format!("block {}", blockid), e))
+}
 
-    fn get_block(&self, blockid: &HeaderHash) -> Result<Block, reqwest::Error>;
+/// Record the latency of a request to `route`, and count it if it fails.
+pub fn measure<T, F>(route: &'static str, request: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    let start = Instant::now();
+    let result = request();
 
-    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, reqwest::Error>;
+    metrics::observe(metrics::BRIDGE_DURATION, &[("route", route)], start.elapsed());
+    if result.is_err() {
+        metrics::inc(metrics::BRIDGE_ERRORS, &[("route", route)], 1.0);
+    }
 
+    result
 }
 
 impl HttpBridgeApi for HttpBridge {
     fn new(url: String) -> Self {
-        HttpBridge {
-            url,
-        }
+        HttpBridge::with_options(url, HttpBridgeOptions::default())
     }
 
-    fn get_tip(&self) -> std::result::Result<BlockHeader, reqwest::Error> {
-        let query = format!("{}tip", self.url);
-        let mut resp = reqwest::get(&query)?;
-        let mut buf: Vec<u8> = vec![];
-        resp.copy_to(&mut buf)?;
+    fn get_tip(&self) -> std::result::Result<BlockHeader, Error> {
+        let buf = self.fetch("tip", "tip")?;
 
         let raw_header_block = block::RawBlockHeader(buf);
-        Ok(raw_header_block.decode().unwrap())
+        raw_header_block
+            .decode()
+            .map_err(|e| Error::DecodeError("tip header".to_string(), e))
     }
 
-    fn get_block(&self, blockid: &HeaderHash) -> Result<Block, reqwest::Error> {
-        let query = format!("{}block/{}", self.url, blockid);
-        let mut resp = reqwest::get(&query)?;
-        let mut buf: Vec<u8> = vec![];
-        resp.copy_to(&mut buf)?;
+    fn get_raw_block(&self, blockid: &HeaderHash) -> Result<block::RawBlock, Error> {
+        let buf = self.fetch("block", &format!("block/{}", blockid))?;
 
-        let raw_block = block::RawBlock(buf);
-        Ok(raw_block.decode().unwrap())
+        Ok(block::RawBlock(buf))
     }
 
-    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, reqwest::Error>
-    {
-        let query = format!("{}epoch/{}", self.url, id);
-        let mut resp = reqwest::get(&query)?;
-        let mut buf: Vec<u8> = vec![];
-        resp.copy_to(&mut buf)?;
+    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, Error> {
+        self.fetch("epoch", &format!("epoch/{}", id))
+    }
 
-        Ok(buf)
+    fn send_transaction(&self, raw: &[u8]) -> Result<(), Error> {
+        let body = format!("{{\"signedTx\":\"{}\"}}", base64::encode(raw));
+        self.send("transaction", "txs/signed", Some(&body))?;
+        Ok(())
+    }
+}
+
+/// The source of blocks selected in the settings.
+#[derive(Clone)]
+pub enum Bridge {
+    Http(HttpBridge),
+    Local(LocalBridge),
+}
+
+impl HttpBridgeApi for Bridge {
+    fn new(url: String) -> Self {
+        Bridge::Http(HttpBridge::new(url))
+    }
+
+    fn get_tip(&self) -> Result<BlockHeader, Error> {
+        match self {
+            Bridge::Http(bridge) => bridge.get_tip(),
+            Bridge::Local(bridge) => bridge.get_tip(),
+        }
+    }
+
+    fn get_raw_block(&self, blockid: &HeaderHash) -> Result<block::RawBlock, Error> {
+        match self {
+            Bridge::Http(bridge) => bridge.get_raw_block(blockid),
+            Bridge::Local(bridge) => bridge.get_raw_block(blockid),
+        }
+    }
+
+    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, Error> {
+        match self {
+            Bridge::Http(bridge) => bridge.get_epoch(id),
+            Bridge::Local(bridge) => bridge.get_epoch(id),
+        }
+    }
+
+    fn send_transaction(&self, raw: &[u8]) -> Result<(), Error> {
+        match self {
+            Bridge::Http(bridge) => bridge.send_transaction(raw),
+            Bridge::Local(bridge) => bridge.send_transaction(raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bridge::TestBridge;
+    use iron::status;
+    use std::path::Path;
+    use std::sync::atomic::Ordering;
+
+    fn test_bridge() -> TestBridge {
+        TestBridge::start(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/synthetic"),
+            0,
+        )
+    }
+
+    fn options(retries: u32, min_interval: Duration) -> HttpBridgeOptions {
+        HttpBridgeOptions {
+            timeout: Duration::from_secs(5),
+            retries,
+            backoff: Duration::from_millis(1),
+            min_interval,
+        }
+    }
+
+    fn status_error(status: u16) -> Error {
+        Error::StatusError(
+            "http://bridge/".to_string(),
+            reqwest::StatusCode::from_u16(status).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(status_error(500).is_transient());
+        assert!(status_error(503).is_transient());
+        assert!(status_error(429).is_transient());
+        assert!(!status_error(404).is_transient());
+        assert!(!status_error(400).is_transient());
+
+        // nothing listens on the discard port
+        let refused = reqwest::get("http://127.0.0.1:9/").unwrap_err();
+        assert!(Error::ConnectionError(refused).is_transient());
+
+        assert!(!Error::NotSupported("submit".to_string()).is_transient());
+        assert!(!Error::StorageError(std::io::ErrorKind::NotFound.into()).is_transient());
+    }
+
+    #[test]
+    fn test_retries() {
+        let test_bridge = test_bridge();
+        let bridge = HttpBridge::with_options(
+            test_bridge.url.clone(),
+            options(2, Duration::from_millis(0)),
+        );
+        let requests = || test_bridge.requests.load(Ordering::SeqCst);
+        let fail = |statuses: &[status::Status]| {
+            test_bridge
+                .failures
+                .lock()
+                .unwrap()
+                .extend(statuses.iter().cloned())
+        };
+
+        // transient failures are retried
+        fail(&[status::InternalServerError, status::TooManyRequests]);
+        assert!(bridge.get_tip().unwrap().compute_hash() == test_bridge.tip());
+        assert!(requests() == 3);
+
+        // up to `retries` times
+        fail(&[status::InternalServerError; 3]);
+        match bridge.get_tip() {
+            Err(Error::StatusError(_, status)) => assert!(status.as_u16() == 500),
+            other => panic!("unexpected result {:?}", other.map(|tip| tip.compute_hash())),
+        }
+        assert!(requests() == 6);
+
+        // the others fail right away
+        fail(&[status::NotFound]);
+        match bridge.get_epoch(0) {
+            Err(Error::StatusError(_, status)) => assert!(status.as_u16() == 404),
+            other => panic!("unexpected result {:?}", other.map(|pack| pack.len())),
+        }
+        assert!(requests() == 7);
+        assert!(bridge.get_epoch(0).is_ok());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let interval = Duration::from_millis(100);
+        let test_bridge = test_bridge();
+        let bridge = HttpBridge::with_options(test_bridge.url.clone(), options(0, interval));
+
+        let start = Instant::now();
+        bridge.throttle();
+
+        let waiting = bridge.clone();
+        let handle = thread::spawn(move || waiting.throttle());
+
+        // the clone waits for its slot without holding the lock
+        thread::sleep(interval / 4);
+        assert!(bridge.last_request.try_lock().is_ok());
+
+        handle.join().unwrap();
+        bridge.throttle();
+        assert!(start.elapsed() >= interval * 2);
     }
 }
@@ -0,0 +1,274 @@
+use cardano::block::block::BlockHeader;
+use cardano::block::types::HeaderHash;
+use cardano::block::RawBlock;
+use cardano::util::hex;
+use storage_units::indexfile::{Lookup, ReaderNoLookup};
+use storage_units::packfile::Seeker;
+
+use crate::http_bridge::{decode_block, measure, Error, HttpBridgeApi};
+use crate::EpochId;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// The pack of an epoch and the lookup tables of its index, which tell
+/// whether a block may be in the pack without reading the index itself.
+struct PackIndex {
+    pack: String,
+    lookup: Lookup,
+}
+
+/// Reads blocks from the storage directory of a cardano-http-bridge, the one
+/// holding its `blob`, `pack`, `index`, `epoch` and `tag` directories.
+#[derive(Clone)]
+pub struct LocalBridge {
+    path: PathBuf,
+    // packs are never rewritten, so their lookups are loaded once
+    indexes: Arc<Mutex<HashMap<EpochId, Arc<PackIndex>>>>,
+}
+
+fn invalid_data<E: std::fmt::Debug>(error: E) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error)).into()
+}
+
+/// Read a hash stored in a tag or epoch file, which hold its 32 bytes.
+fn read_hash(path: &Path) -> Result<String, Error> {
+    let content = fs::read(path)?;
+
+    if content.len() != 32 {
+        return Err(invalid_data(format!(
+            "{} holds {} bytes instead of a hash",
+            path.display(),
+            content.len()
+        )));
+    }
+
+    Ok(hex::encode(&content))
+}
+
+impl LocalBridge {
+    fn epochs(&self) -> Result<Vec<EpochId>, Error> {
+        let mut epochs = vec![];
+
+        for entry in fs::read_dir(self.path.join("epoch"))? {
+            if let Ok(epoch) = entry?.file_name().to_string_lossy().parse() {
+                epochs.push(epoch);
+            }
+        }
+
+        epochs.sort();
+        Ok(epochs)
+    }
+
+    fn pack_index(&self, epoch: EpochId) -> Result<Arc<PackIndex>, Error> {
+        if let Some(index) = self.indexes.lock().unwrap().get(&epoch) {
+            return Ok(index.clone());
+        }
+
+        let pack = read_hash(&self.path.join("epoch").join(epoch.to_string()).join("pack"))?;
+        let mut file = fs::File::open(self.path.join("index").join(&pack))?;
+        let lookup = Lookup::read_from_file(&mut file).map_err(invalid_data)?;
+
+        let index = Arc::new(PackIndex { pack, lookup });
+        self.indexes.lock().unwrap().insert(epoch, index.clone());
+        Ok(index)
+    }
+
+    /// Look `blockid` up in the index of every epoch pack, newest first,
+    /// reading only the block found.
+    fn packed_block(&self, blockid: &HeaderHash) -> Result<Option<Vec<u8>>, Error> {
+        let hash = blockid.as_hash_bytes();
+
+        for epoch in self.epochs()?.into_iter().rev() {
+            let index = self.pack_index(epoch)?;
+            let lookup = &index.lookup;
+
+            let (start, count) = lookup.fanout.get_indexer_by_hash(hash);
+            if count.0 == 0 || !lookup.bloom.search(hash) {
+                continue;
+            }
+
+            let mut reader = ReaderNoLookup::init(self.path.join("index").join(&index.pack))
+                .map_err(invalid_data)?;
+            if let Some(offset) = reader.search(&lookup.params, hash, start, count) {
+                let offset = reader.resolve_index_offset(lookup, offset);
+                let mut seeker =
+                    Seeker::init(self.path.join("pack").join(&index.pack)).map_err(invalid_data)?;
+                return Ok(Some(seeker.get_at_offset(offset).map_err(invalid_data)?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn raw_block(&self, blockid: &HeaderHash) -> Result<RawBlock, Error> {
+        // recent blocks are kept as loose blobs until their epoch is packed
+        match fs::read(self.path.join("blob").join(format!("{}", blockid))) {
+            Ok(raw) => return Ok(RawBlock(raw)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        match self.packed_block(blockid)? {
+            Some(raw) => Ok(RawBlock(raw)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("block {} not found in storage", blockid),
+            )
+            .into()),
+        }
+    }
+}
+
+impl HttpBridgeApi for LocalBridge {
+    fn new(path: String) -> Self {
+        LocalBridge {
+            path: PathBuf::from(path),
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // measured under the same routes as the requests of the http bridge
+    fn get_tip(&self) -> Result<BlockHeader, Error> {
+        measure("tip", || {
+            let head = read_hash(&self.path.join("tag").join("HEAD"))?;
+            let hash = HeaderHash::from_str(&head).map_err(invalid_data)?;
+
+            Ok(decode_block(&hash, &self.raw_block(&hash)?)?.header())
+        })
+    }
+
+    fn get_raw_block(&self, blockid: &HeaderHash) -> Result<RawBlock, Error> {
+        measure("block", || self.raw_block(blockid))
+    }
+
+    fn get_epoch(&self, id: EpochId) -> Result<Vec<u8>, Error> {
+        measure("epoch", || {
+            let epoch_dir = self.path.join("epoch").join(id.to_string());
+            let pack = read_hash(&epoch_dir.join("pack"))?;
+
+            Ok(fs::read(self.path.join("pack").join(pack))?)
+        })
+    }
+
+    fn send_transaction(&self, _: &[u8]) -> Result<(), Error> {
+        Err(Error::NotSupported(
+            "submit transactions from a storage directory".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage_units::packfile::{Reader, Writer};
+    use storage_units::utils::tmpfile::TmpFile;
+
+    /// A storage directory holding the chain of `tests/fixtures/synthetic`
+    /// as the http-bridge keeps it: every epoch but the last packed and
+    /// indexed, the blocks of the last one as blobs. Returns the blocks in
+    /// chain order.
+    fn storage(path: &Path) -> Vec<(HeaderHash, Vec<u8>)> {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/synthetic");
+        let _ = fs::remove_dir_all(path);
+        for dir in &["blob", "pack", "index", "epoch", "tag"] {
+            fs::create_dir_all(path.join(dir)).unwrap();
+        }
+
+        let mut epochs = vec![];
+        while let Ok(pack) = fs::read(fixture.join("epoch").join(epochs.len().to_string())) {
+            let mut reader = Reader::init(pack.as_slice()).unwrap();
+            let mut blocks = vec![];
+            while let Some(raw) = reader.next_block().unwrap() {
+                let hash = RawBlock(raw.clone())
+                    .decode()
+                    .unwrap()
+                    .header()
+                    .compute_hash();
+                blocks.push((hash, raw));
+            }
+            epochs.push(blocks);
+        }
+        let recent = epochs.pop().expect("an epoch in the fixture");
+
+        for (epoch, blocks) in epochs.iter().enumerate() {
+            let mut writer = Writer::init(TmpFile::create(path.join("pack")).unwrap()).unwrap();
+            for (hash, raw) in blocks {
+                writer.append(hash.as_hash_bytes(), raw).unwrap();
+            }
+            let (pack, packhash, index) = writer.finalize().unwrap();
+            let name = hex::encode(&packhash);
+            pack.render_permanent(&path.join("pack").join(&name))
+                .unwrap();
+
+            let mut tmpfile = TmpFile::create(path.join("index")).unwrap();
+            index.write_to_tmpfile(&mut tmpfile).unwrap();
+            tmpfile
+                .render_permanent(&path.join("index").join(&name))
+                .unwrap();
+
+            let epoch_dir = path.join("epoch").join(epoch.to_string());
+            fs::create_dir_all(&epoch_dir).unwrap();
+            fs::write(epoch_dir.join("pack"), &packhash).unwrap();
+        }
+
+        for (hash, raw) in recent.iter() {
+            fs::write(path.join("blob").join(format!("{}", hash)), raw).unwrap();
+        }
+        let (tip, _) = recent.last().unwrap();
+        fs::write(path.join("tag").join("HEAD"), tip.as_hash_bytes()).unwrap();
+
+        epochs.into_iter().flatten().chain(recent).collect()
+    }
+
+    #[test]
+    fn test_read_storage() {
+        let path = std::env::temp_dir().join(format!("local-bridge-{}", std::process::id()));
+        let blocks = storage(&path);
+        let bridge = LocalBridge::new(path.to_string_lossy().into_owned());
+
+        let (tip, _) = blocks.last().unwrap();
+        assert!(bridge.get_tip().unwrap().compute_hash() == *tip);
+
+        // packed blocks are found through the index of their epoch, recent
+        // ones as blobs, in any order
+        for (hash, raw) in blocks.iter().rev().chain(blocks.iter()) {
+            assert!(&bridge.get_raw_block(hash).unwrap().0 == raw);
+        }
+
+        let pack = bridge.get_epoch(0).unwrap();
+        let mut reader = Reader::init(pack.as_slice()).unwrap();
+        assert!(reader.next_block().unwrap().as_ref() == Some(&blocks[0].1));
+
+        let unknown = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+        match bridge.get_raw_block(&unknown) {
+            Err(Error::StorageError(ref e)) if e.kind() == io::ErrorKind::NotFound => (),
+            other => panic!("unexpected result {:?}", other.map(|raw| raw.0)),
+        }
+
+        match bridge.send_transaction(&[0x82]) {
+            Err(Error::NotSupported(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // the storage writes hashes as their bytes, not as hex
+        fs::write(path.join("tag").join("HEAD"), format!("{}", tip)).unwrap();
+        match bridge.get_tip() {
+            Err(Error::StorageError(ref e)) if e.kind() == io::ErrorKind::InvalidData => (),
+            other => panic!(
+                "unexpected result {:?}",
+                other.map(|tip| tip.compute_hash())
+            ),
+        }
+
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -1,4 +1,7 @@
+extern crate base64;
 extern crate cardano;
+extern crate cbor_event;
+extern crate cryptoxide;
 extern crate exe_common;
 extern crate r2d2;
 extern crate r2d2_sqlite;
@@ -10,14 +13,22 @@ extern crate router;
 
 extern crate serde;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 extern crate env_logger;
 
 mod config;
+mod events;
 mod http_bridge;
+mod local_bridge;
+mod metrics;
 mod server;
 mod storage;
+#[cfg(test)]
+mod test_bridge;
 mod types;
+mod webhooks;
 
 use storage::{apply_initial_state, prepare_schema};
 
@@ -26,9 +37,10 @@ use std::sync::Arc;
 use std::{thread, time};
 
 type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
-type Config = config::Config<http_bridge::HttpBridge>;
+type Config = config::Config<http_bridge::Bridge>;
 use clap::{App, SubCommand};
 
+use cardano::block::date::BlockDate;
 use cardano::block::types::EpochId;
 
 use crate::http_bridge::HttpBridgeApi;
@@ -42,38 +54,50 @@ fn main() -> rusqlite::Result<()> {
         .about(clap::crate_description!())
         .subcommand(SubCommand::with_name("start").about("start server"))
         .subcommand(SubCommand::with_name("sync-block-index"))
+        .subcommand(
+            SubCommand::with_name("backfill-block-metadata")
+                .about("record the metadata of blocks imported by older versions"),
+        )
         .get_matches();
 
     let mut settings = ::config::Config::default();
-    settings
+    let config = settings
         .merge(::config::File::with_name("Settings.toml"))
-        .unwrap();
-
-    debug!("Settings :: {:?}", &settings);
-
-    let port: u16 = settings.get("port").unwrap();
-    let bridge_url = format!(
-        "{}{}/",
-        settings.get::<String>("http-bridge").unwrap(),
-        settings.get::<String>("network").unwrap()
-    );
-    let refresh_interval = settings.get("refresh-interval").unwrap();
-    let database: String = settings.get("database").unwrap();
-
-    let manager = r2d2_sqlite::SqliteConnectionManager::file(database);
-    let bridge = http_bridge::HttpBridge::new(bridge_url);
-    let config = Arc::new(Config::new(port, bridge, manager, refresh_interval));
+        .map_err(|e| format!("Could not read Settings.toml: {}", e))
+        .and_then(|settings| {
+            debug!("Settings :: {:?}", &settings);
+            config_from(settings)
+        });
+
+    let config = match config {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     match matches.subcommand() {
         ("start", Some(_)) => {
+            match prepare_schema(&connect_or_exit(&config)) {
+                Err(e) => error!("Error preparing schema {}", e),
+                _ => info!("Schema prepared"),
+            }
+
             let _server = crate::server::start_http_server(config.clone());
+
+            let pool = config.pool.clone();
+            thread::spawn(move || webhooks::run(pool));
+
             loop {
-                let config = config.clone();
+                let sync_config = config.clone();
                 info!("Starting sync thread");
-                let handle = thread::spawn(move || sync(config));
+                let handle = thread::spawn(move || sync(sync_config));
 
                 if let Err(e) = handle.join().unwrap() {
                     error!("Syncing error: {}", e);
+                    config.sync_state.lock().unwrap().last_error =
+                        Some((time::SystemTime::now(), format!("{}", e)));
                 }
 
                 let restart_time = time::Duration::from_secs(5);
@@ -82,81 +106,488 @@ fn main() -> rusqlite::Result<()> {
             }
         }
         ("sync-block-index", Some(_)) => {
-            let mut conn = config.pool.get().unwrap();
+            if let Err(e) = sync_block_index(&config) {
+                error!("Could not import the stable epochs: {}", e);
+            }
+        }
+        ("backfill-block-metadata", Some(_)) => {
+            let mut conn = connect_or_exit(&config);
 
             match prepare_schema(&conn) {
                 Err(e) => error!("Error preparing schema {}", e),
                 _ => info!("Schema prepared"),
             }
 
-            let genesis_hash = &config.genesis_prev;
-
-            let chain_state =
-                cardano::block::ChainState::new(&exe_common::genesisdata::parse::parse(
-                    exe_common::genesisdata::data::get_genesis_data(&genesis_hash)
-                        .unwrap()
-                        .as_bytes(),
-                ));
-
-            match apply_initial_state(&mut conn, &chain_state.utxos) {
-                Err(e) => error!("Could not apply initial state {}", e),
-                _ => info!("Initial state applied"),
+            // only databases of older versions, which followed mainnet, lack
+            // the metadata
+            let genesis = match config.genesis.clone() {
+                Some(genesis) => genesis,
+                None => {
+                    error!("backfill-block-metadata only works on mainnet, staging and testnet");
+                    std::process::exit(1);
+                }
+            };
+
+            let backfilled =
+                storage::backfill_block_metadata(&mut conn, &config.genesis_prev, genesis, |hash| {
+                    Ok(config.bridge.get_raw_block(hash)?)
+                });
+            match backfilled {
+                Ok(count) => info!("Metadata recorded for {} blocks", count),
+                Err(e) => error!("Could not backfill block metadata {}", e),
             }
+        }
+        _ => error!("Unrecognized argument"),
+    };
 
-            let first_unstable_epoch = config
-                .bridge
-                .first_unstable_epoch(config.epoch_stability_depth)
-                .unwrap();
+    Ok(())
+}
 
-            info!("First unstable epoch: {}", first_unstable_epoch);
+/// A required setting, which must be present and of type `T`.
+fn setting<T>(settings: &::config::Config, key: &str) -> Result<T, String>
+where
+    T: serde::de::DeserializeOwned,
+{
+    settings
+        .get(key)
+        .map_err(|e| format!("Invalid setting {}: {}", key, e))
+}
 
-            storage::sync_from_epochs(&mut conn, first_unstable_epoch, |id: EpochId| {
-                config.bridge.get_epoch(id).unwrap()
-            })
-            .unwrap();
+/// The configuration set in `settings`, or why it is invalid.
+fn config_from(settings: &::config::Config) -> Result<Config, String> {
+    let port: u16 = setting(settings, "port")?;
+    let bridge = match settings
+        .get::<String>("bridge")
+        .unwrap_or_else(|_| "http".to_string())
+        .as_str()
+    {
+        "http" => {
+            let defaults = http_bridge::HttpBridgeOptions::default();
+            let millis = |key: &str, default: time::Duration| {
+                settings
+                    .get::<u64>(key)
+                    .map(time::Duration::from_millis)
+                    .unwrap_or(default)
+            };
+            // the rate limit is in requests per second, 0 for none
+            let rate_limit = settings.get::<f64>("bridge-rate-limit").unwrap_or(0.0);
+
+            let options = http_bridge::HttpBridgeOptions {
+                timeout: millis("bridge-timeout", defaults.timeout),
+                retries: settings.get("bridge-retries").unwrap_or(defaults.retries),
+                backoff: millis("bridge-backoff", defaults.backoff),
+                min_interval: if rate_limit > 0.0 {
+                    time::Duration::from_micros((1_000_000.0 / rate_limit) as u64)
+                } else {
+                    defaults.min_interval
+                },
+            };
+
+            http_bridge::Bridge::Http(http_bridge::HttpBridge::with_options(
+                format!(
+                    "{}{}/",
+                    setting::<String>(settings, "http-bridge")?,
+                    setting::<String>(settings, "network")?
+                ),
+                options,
+            ))
         }
-        _ => error!("Unrecognized argument"),
+        "storage" => {
+            let path: String = setting(settings, "bridge-storage")?;
+            http_bridge::Bridge::Local(local_bridge::LocalBridge::new(path))
+        }
+        other => return Err(format!("Unknown bridge {}, expected http or storage", other)),
+    };
+    let refresh_interval = setting(settings, "refresh-interval")?;
+    let sync_batch_size = settings.get("sync-batch-size").unwrap_or(1000);
+    let sync_batch_interval = settings.get("sync-batch-interval").unwrap_or(10000);
+    let stream_max_clients = settings.get("stream-max-clients").unwrap_or(100);
+    let admin_token = settings.get("admin-token").ok();
+    let pending_timeout = settings.get("pending-tx-timeout").unwrap_or(7200);
+    let expired_retention = settings.get("expired-tx-retention").unwrap_or(604800);
+    let import_pipeline = storage::Pipeline {
+        workers: settings
+            .get("import-workers")
+            .unwrap_or(storage::Pipeline::default().workers),
+        max_pending_epochs: settings
+            .get("import-pending-epochs")
+            .unwrap_or(storage::Pipeline::default().max_pending_epochs),
     };
+    let database: String = setting(settings, "database")?;
 
-    Ok(())
+    let manager = r2d2_sqlite::SqliteConnectionManager::file(database);
+    let network = config::Network::new(
+        &setting::<String>(settings, "network")?,
+        settings.get("genesis-file").ok(),
+    )?;
+
+    Config::new(
+        network,
+        port,
+        bridge,
+        manager,
+        refresh_interval,
+        sync_batch_size,
+        sync_batch_interval,
+        stream_max_clients,
+        admin_token,
+        pending_timeout,
+        expired_retention,
+        import_pipeline,
+    )
+}
+
+/// A connection of the pool, for the commands which can't do anything
+/// without the database. Exits if none can be opened.
+fn connect_or_exit(
+    config: &Config,
+) -> r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager> {
+    match config.pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Could not open the database: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn sync_block_index(config: &Config) -> Result<(), types::Error> {
+    let mut conn = config.pool.get()?;
+
+    match prepare_schema(&conn) {
+        Err(e) => error!("Error preparing schema {}", e),
+        _ => info!("Schema prepared"),
+    }
+
+    let chain_state = cardano::block::ChainState::new(&exe_common::genesisdata::parse::parse(
+        config.genesis_data.as_bytes(),
+    ));
+
+    match apply_initial_state(&mut conn, &chain_state.utxos) {
+        Err(e) => error!("Could not apply initial state {}", e),
+        _ => info!("Initial state applied"),
+    }
+
+    let first_unstable_epoch = config
+        .bridge
+        .first_unstable_epoch(config.epoch_stability_depth)?;
+
+    info!("First unstable epoch: {}", first_unstable_epoch);
+
+    let bridge = config.bridge.clone();
+    storage::sync_from_epochs(
+        &mut conn,
+        &config.genesis_prev,
+        first_unstable_epoch,
+        &config.import_pipeline,
+        move |id: EpochId| Ok(bridge.get_epoch(id)?),
+    )
 }
 
 use std::result::Result;
 fn sync(config: Arc<Config>) -> Result<(), types::Error> {
-    let mut conn = config.pool.get().unwrap();
+    let mut conn = config.pool.get()?;
 
     loop {
-        let tip = config.bridge.get_tip()?;
+        sync_once(&config, &mut conn)?;
+        config.sync_state.lock().unwrap().last_success = Some(time::SystemTime::now());
 
-        info!("Tip is {}", tip.compute_hash());
+        thread::sleep(time::Duration::from_millis(config.refresh_interval));
+    }
+}
+
+/// Follow the bridge tip once, applying the blocks leading to it. Returns the
+/// number of blocks applied.
+///
+/// The blocks are applied in batches of `sync_batch_size` blocks or
+/// `sync_batch_interval` milliseconds, whichever comes first. The block index
+/// update, with the rollback of a fork, is committed along with the first
+/// batch, so a fork is never left half switched. Each batch commits the
+/// `last_block` it reached, so a failure only loses the current batch.
+fn sync_once(config: &Config, conn: &mut rusqlite::Connection) -> Result<usize, types::Error> {
+    let tip = match config.bridge.get_tip() {
+        Ok(tip) => tip,
+        Err(e) => {
+            config.sync_state.lock().unwrap().bridge_error = Some(format!("{}", e));
+            return Err(e.into());
+        }
+    };
+
+    info!("Tip is {}", tip.compute_hash());
 
-        storage::update_block_index(&mut conn, tip.compute_hash(), |blockid: HeaderHash| {
+    // committed with the first batch
+    let mut transaction = conn.transaction()?;
+
+    let reverted = storage::update_block_index(
+        &transaction,
+        tip.compute_hash(),
+        |blockid: HeaderHash| {
             Ok(config
                 .bridge
                 .get_block(&blockid)?
                 .header()
                 .previous_header())
-        })?;
+        },
+    )?;
+
+    info!("Block index updated");
+
+    let mut block_hash = storage::last_applied_block(&transaction)?.ok_or_else(|| {
+        types::Error::InvariantViolation(
+            "no block applied yet, sync-block-index has to be run first".to_string(),
+        )
+    })?;
+
+    // the rollback moved the last applied block back to the ancestor
+    let mut rollback = if reverted.is_empty() {
+        None
+    } else {
+        warn!("{} blocks rolled back", reverted.len());
+        Some(types::Rollback {
+            ancestor: format!("{}", block_hash),
+            reverted: reverted.iter().map(|hash| format!("{}", hash)).collect(),
+        })
+    };
 
-        info!("Block index updated");
+    let mut lag: i32 = 0;
+    let mut ahead = block_hash.clone();
+    while let Some(next) = storage::next_block(&transaction, ahead)? {
+        lag += 1;
+        ahead = next;
+    }
+    metrics::set(metrics::SYNC_LAG, &[], f64::from(lag));
 
-        let mut block_hash = storage::last_applied_block(&conn)?.unwrap();
+    // kept for `/status`, which doesn't query the bridge itself
+    let (epoch, slot) = match tip.get_blockdate() {
+        BlockDate::Boundary(epoch) => (epoch as i64, None),
+        BlockDate::Normal(date) => (date.epoch as i64, Some(i64::from(date.slotid))),
+    };
+    let tip_height =
+        storage::last_applied_height(&transaction)?.map(|height| height + i64::from(lag));
+    {
+        let mut sync_state = config.sync_state.lock().unwrap();
+        sync_state.tip = Some(types::BlockStatus {
+            hash: format!("{}", tip.compute_hash()),
+            epoch: Some(epoch),
+            slot,
+        });
+        sync_state.tip_height = tip_height;
+        sync_state.bridge_error = None;
+    }
 
-        let transaction = conn.transaction().unwrap();
+    let batch_interval = time::Duration::from_millis(config.sync_batch_interval);
+    let mut counter = 0;
 
-        let mut counter = 0;
-        while let Some(next) = storage::next_block(&transaction, block_hash)? {
-            counter = counter + 1;
-            let block = config.bridge.get_block(&next)?;
-            storage::apply_block(&transaction, &block)?;
+    loop {
+        let batch_start = time::Instant::now();
+        let mut batch = 0;
+        let mut applied = vec![];
+
+        // a batch holds at least one block whatever the settings
+        while batch == 0
+            || (batch < config.sync_batch_size && batch_start.elapsed() < batch_interval)
+        {
+            let next = match storage::next_block(&transaction, block_hash.clone())? {
+                Some(next) => next,
+                None => break,
+            };
+
+            let raw = config.bridge.get_raw_block(&next)?;
+            let block = http_bridge::decode_block(&next, &raw)?;
+            storage::apply_block(&transaction, &config.genesis_prev, &block, &raw.0)?;
+            applied.push(storage::block_activity(&transaction, &next)?);
             block_hash = next;
+            batch += 1;
+
+            let transactions = block.get_transactions().map_or(0, |txs| txs.into_iter().count());
+            metrics::inc(metrics::TRANSACTIONS_APPLIED, &[], transactions as f64);
         }
 
+        // queued with the blocks, so no payload is lost if the batch fails
+        storage::queue_confirmed(&transaction)?;
+        storage::prune_pending(
+            &transaction,
+            config.pending_timeout,
+            config.expired_retention,
+            config.epoch_stability_depth,
+        )?;
         transaction.commit()?;
 
-        info!("{} blocks applied", counter);
-        info!("new head: {}", storage::last_applied_block(&conn)?.unwrap());
+        // announced once committed, so the streams never see a rollback or
+        // a block a failed batch leaves out
+        if let Some(rollback) = rollback.take() {
+            config.events.publish(events::Event::Rollback(rollback));
+        }
+        for activity in applied {
+            config.events.publish(events::Event::Block(activity));
+        }
+
+        if batch == 0 {
+            break;
+        }
 
-        thread::sleep(time::Duration::from_millis(config.refresh_interval));
+        counter += batch;
+        metrics::inc(metrics::BLOCKS_APPLIED, &[], batch as f64);
+        metrics::set(metrics::SYNC_LAG, &[], f64::from(lag) - counter as f64);
+        info!("{} of {} blocks applied, head: {}", counter, lag, block_hash);
+
+        transaction = conn.transaction()?;
+    }
+
+    info!("{} blocks applied", counter);
+    info!("new head: {}", block_hash);
+
+    Ok(counter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_bridge::{Bridge, HttpBridge};
+    use crate::test_bridge::TestBridge;
+    use serde_json::Value;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/synthetic")
+    }
+
+    fn get(url: &str) -> Value {
+        let mut response = reqwest::get(url).unwrap();
+        assert!(response.status().is_success(), "GET {}", url);
+        response.json().unwrap()
+    }
+
+    fn get_bytes(url: &str) -> Vec<u8> {
+        let mut response = reqwest::get(url).unwrap();
+        assert!(response.status().is_success(), "GET {}", url);
+        let mut body = vec![];
+        response.copy_to(&mut body).unwrap();
+        body
+    }
+
+    /// A bridge serving the chain of `tests/fixtures/synthetic`, and the
+    /// config of an importer following it into a database of its own.
+    fn fixture_config(name: &str) -> (TestBridge, Arc<Config>, PathBuf) {
+        let genesis = fixture().join("genesis.json");
+        let depth = exe_common::genesisdata::parse::parse(&fs::read(&genesis).unwrap())
+            .epoch_stability_depth;
+        // the tip is past the stability depth of the last epoch, so the ones
+        // before are imported packed and its blocks by the sync loop
+        let bridge = TestBridge::start(&fixture(), depth + 2);
+
+        let database = std::env::temp_dir().join(format!(
+            "transaction-importer-{}-{}.sqlite",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&database);
+
+        let config = Config::new(
+            config::Network::Custom(genesis),
+            0,
+            Bridge::Http(HttpBridge::new(bridge.url.clone())),
+            r2d2_sqlite::SqliteConnectionManager::file(&database),
+            0,
+            1000,
+            10000,
+            100,
+            None,
+            7200,
+            604800,
+            storage::Pipeline::default(),
+        )
+        .unwrap();
+
+        (bridge, Arc::new(config), database)
+    }
+
+    /// Import the stable epochs of the fixture, then sync up to its tip.
+    fn sync_fixture(bridge: &TestBridge, config: &Config) {
+        if let Err(e) = sync_block_index(config) {
+            panic!("import failed: {}", e);
+        }
+
+        let mut conn = config.pool.get().unwrap();
+        if let Err(e) = sync_once(config, &mut conn) {
+            panic!("sync failed: {}", e);
+        }
+        assert!(storage::last_applied_block(&conn).unwrap() == Some(bridge.tip()));
+    }
+
+    /// Sync from the chain of `tests/fixtures/synthetic` and query the server,
+    /// checking every transaction of the chain and the responses in
+    /// `expected/`. Set `UPDATE_EXPECTED` to rewrite the expected responses.
+    #[test]
+    fn test_sync_from_bridge() {
+        let (bridge, config, database) = fixture_config("sync");
+        sync_fixture(&bridge, &config);
+
+        let server = server::start_http_server(config.clone());
+        let url = format!("http://127.0.0.1:{}", server.socket.port());
+
+        for block in &bridge.blocks {
+            let hash = format!("{}", block.header().compute_hash());
+
+            // the size of the bytes served, whatever a re-encoding would give
+            let served = get_bytes(&format!("{}block/{}", bridge.url, hash));
+            let indexed = get(&format!("{}/block/{}", url, hash));
+            assert!(indexed["size"] == Value::from(served.len()));
+
+            for (index, tx_aux) in block.get_transactions().into_iter().flatten().enumerate() {
+                let txid = format!("{}", tx_aux.tx.id());
+
+                let transaction = get(&format!("{}/transaction/{}", url, txid));
+                assert!(transaction["txid"] == Value::from(txid.clone()));
+                assert!(transaction["block"] == Value::from(hash.clone()));
+                assert!(transaction["index_in_block"] == Value::from(index));
+                assert!(
+                    transaction["outputs"].as_array().unwrap().len() == tx_aux.tx.outputs.len()
+                );
+
+                // the three formats of the stored encoding agree, and the json
+                // of the ones in `expected/` pins it to the block's bytes
+                let raw = format!("{}/transaction/{}/raw", url, txid);
+                let decoded = get(&format!("{}?format=json", raw));
+                let cbor = get_bytes(&raw);
+                let hex = get_bytes(&format!("{}?format=hex", raw));
+                assert!(decoded["cbor"] == Value::from(cardano::util::hex::encode(&cbor)));
+                assert!(hex == decoded["cbor"].as_str().unwrap().as_bytes());
+                assert!(decoded["witnesses"].as_array().unwrap().len() == tx_aux.witness.len());
+
+                for output in tx_aux.tx.outputs.iter() {
+                    let history = get(&format!("{}/transactions/{}", url, output.address));
+                    assert!(history
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .any(|transaction| transaction["txid"] == Value::from(txid.clone())));
+                }
+            }
+        }
+
+        let update = std::env::var("UPDATE_EXPECTED").is_ok();
+        let expected_dir = fixture().join("expected");
+        let entries = fs::read_dir(&expected_dir)
+            .unwrap_or_else(|e| panic!("{}: {}", expected_dir.display(), e))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(!entries.is_empty(), "no response in {}", expected_dir.display());
+
+        for entry in entries {
+            let path = entry.path();
+            let mut expected: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+            let response = get(&format!("{}{}", url, expected["path"].as_str().unwrap()));
+
+            if update {
+                expected["response"] = response;
+                fs::write(&path, serde_json::to_string_pretty(&expected).unwrap()).unwrap();
+            } else {
+                assert!(response == expected["response"], "{}", path.display());
+            }
+        }
+
+        let _ = fs::remove_file(&database);
     }
 }
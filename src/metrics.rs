@@ -0,0 +1,313 @@
+//! Process wide metrics, exposed in the Prometheus text format at `/metrics`.
+
+use iron::request::Request;
+use iron::response::Response;
+use iron::IronResult;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const BLOCKS_APPLIED: &str = "importer_blocks_applied_total";
+pub const TRANSACTIONS_APPLIED: &str = "importer_transactions_applied_total";
+pub const SYNC_LAG: &str = "importer_sync_lag_blocks";
+pub const BRIDGE_DURATION: &str = "importer_bridge_request_duration_seconds";
+pub const BRIDGE_ERRORS: &str = "importer_bridge_request_errors_total";
+pub const POOL_CONNECTIONS: &str = "importer_sqlite_pool_connections";
+pub const POOL_IDLE_CONNECTIONS: &str = "importer_sqlite_pool_idle_connections";
+pub const POOL_MAX_SIZE: &str = "importer_sqlite_pool_max_size";
+pub const HTTP_REQUESTS: &str = "importer_http_requests_total";
+pub const HTTP_DURATION: &str = "importer_http_request_duration_seconds";
+pub const STREAM_CLIENTS: &str = "importer_stream_clients";
+pub const WEBHOOK_DELIVERIES: &str = "importer_webhook_deliveries_total";
+
+/// Name, type and help of every metric, in exposition order.
+const DESCRIPTIONS: &[(&str, &str, &str)] = &[
+    (BLOCKS_APPLIED, "counter", "Blocks applied by the sync loop"),
+    (TRANSACTIONS_APPLIED, "counter", "Transactions applied by the sync loop"),
+    (SYNC_LAG, "gauge", "Blocks between the last applied block and the bridge tip"),
+    (BRIDGE_DURATION, "histogram", "Latency of the requests to the bridge"),
+    (BRIDGE_ERRORS, "counter", "Failed requests to the bridge"),
+    (POOL_CONNECTIONS, "gauge", "Connections opened by the sqlite pool"),
+    (POOL_IDLE_CONNECTIONS, "gauge", "Idle connections of the sqlite pool"),
+    (POOL_MAX_SIZE, "gauge", "Maximum size of the sqlite pool"),
+    (HTTP_REQUESTS, "counter", "Requests served, by handler and status"),
+    (HTTP_DURATION, "histogram", "Latency of the requests served, by handler"),
+    (STREAM_CLIENTS, "gauge", "Clients following the address stream"),
+    (WEBHOOK_DELIVERIES, "counter", "Attempts to deliver a webhook payload, by result"),
+];
+
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Registry {
+    values: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn labels(labels: &[(&'static str, &str)]) -> Labels {
+    labels
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .collect()
+}
+
+pub fn inc(name: &'static str, label_values: &[(&'static str, &str)], by: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    *registry
+        .values
+        .entry((name, labels(label_values)))
+        .or_insert(0.0) += by;
+}
+
+pub fn set(name: &'static str, label_values: &[(&'static str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.values.insert((name, labels(label_values)), value);
+}
+
+pub fn observe(name: &'static str, label_values: &[(&'static str, &str)], duration: Duration) {
+    let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+
+    let mut registry = REGISTRY.lock().unwrap();
+    let histogram = registry
+        .histograms
+        .entry((name, labels(label_values)))
+        .or_insert_with(|| Histogram {
+            counts: vec![0; BUCKETS.len()],
+            ..Histogram::default()
+        });
+
+    for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter_mut()) {
+        if seconds <= *bound {
+            *count += 1;
+        }
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+fn format_labels(labels: &[(&str, String)], extra: Option<(&str, String)>) -> String {
+    let formatted: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| (*name, value.clone()))
+        .chain(extra)
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+
+    if formatted.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", formatted.join(","))
+    }
+}
+
+/// All the metrics recorded so far, in the Prometheus text format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut output = String::new();
+
+    for (name, kind, help) in DESCRIPTIONS {
+        writeln!(output, "# HELP {} {}", name, help).unwrap();
+        writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+
+        let values = registry.values.iter().filter(|((n, _), _)| n == name);
+        for ((_, labels), value) in values {
+            writeln!(output, "{}{} {}", name, format_labels(labels, None), value).unwrap();
+        }
+
+        let histograms = registry.histograms.iter().filter(|((n, _), _)| n == name);
+        for ((_, labels), histogram) in histograms {
+            for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter()) {
+                let le = Some(("le", bound.to_string()));
+                writeln!(output, "{}_bucket{} {}", name, format_labels(labels, le), count)
+                    .unwrap();
+            }
+            let le = Some(("le", "+Inf".to_string()));
+            writeln!(output, "{}_bucket{} {}", name, format_labels(labels, le), histogram.count)
+                .unwrap();
+            writeln!(output, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum)
+                .unwrap();
+            writeln!(output, "{}_count{} {}", name, format_labels(labels, None), histogram.count)
+                .unwrap();
+        }
+    }
+
+    output
+}
+
+/// Wraps a handler to count its requests by status and record their latency.
+pub struct Instrumented<H> {
+    name: &'static str,
+    handler: H,
+}
+
+pub fn instrument<H: iron::Handler>(name: &'static str, handler: H) -> Instrumented<H> {
+    Instrumented { name, handler }
+}
+
+impl<H: iron::Handler> iron::Handler for Instrumented<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let start = Instant::now();
+        let result = self.handler.handle(req);
+
+        let status = match result {
+            Ok(ref response) => response.status,
+            Err(ref error) => error.response.status,
+        };
+        let status = status.map_or(0, |status| status.to_u16()).to_string();
+
+        inc(HTTP_REQUESTS, &[("handler", self.name), ("status", &status)], 1.0);
+        observe(HTTP_DURATION, &[("handler", self.name)], start.elapsed());
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iron::status;
+    use iron::{Iron, IronError};
+    use std::io;
+
+    // the registry is shared by the whole process, so every test uses labels
+    // of its own
+
+    fn lines(route: &str) -> Vec<String> {
+        render()
+            .lines()
+            .filter(|line| line.contains(route))
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_render_format() {
+        inc(BRIDGE_ERRORS, &[("route", "test_format")], 1.0);
+        inc(BRIDGE_ERRORS, &[("route", "test_format")], 2.0);
+        set(STREAM_CLIENTS, &[], 3.0);
+
+        let output = render();
+        let mut header = output.lines();
+        assert!(
+            header.next()
+                == Some("# HELP importer_blocks_applied_total Blocks applied by the sync loop")
+        );
+        assert!(header.next() == Some("# TYPE importer_blocks_applied_total counter"));
+
+        // every metric is described, and every sample is a name, its labels
+        // and a number
+        for (name, kind, _) in DESCRIPTIONS {
+            assert!(output.contains(&format!("# TYPE {} {}\n", name, kind)));
+        }
+        for line in output.lines().filter(|line| !line.starts_with('#')) {
+            let value = line.rsplit(' ').next().unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", line);
+        }
+
+        assert!(
+            lines("test_format")
+                == vec!["importer_bridge_request_errors_total{route=\"test_format\"} 3"]
+        );
+        assert!(output.contains("\nimporter_stream_clients 3\n"));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        inc(BRIDGE_ERRORS, &[("route", "test_escaping \"a\\b\"\nc")], 1.0);
+
+        let escaped =
+            r#"importer_bridge_request_errors_total{route="test_escaping \"a\\b\"\nc"} 1"#;
+        assert!(lines("test_escaping") == vec![escaped]);
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let route = [("route", "test_buckets")];
+        // durations exact in binary, so the sum is too
+        observe(BRIDGE_DURATION, &route, Duration::from_micros(31_250));
+        observe(BRIDGE_DURATION, &route, Duration::from_millis(375));
+        observe(BRIDGE_DURATION, &route, Duration::from_secs(64));
+
+        let name = "importer_bridge_request_duration_seconds";
+        let bucket = |le: &str, count: u64| {
+            format!("{}_bucket{{route=\"test_buckets\",le=\"{}\"}} {}", name, le, count)
+        };
+
+        let mut expected = vec![
+            bucket("0.005", 0),
+            bucket("0.01", 0),
+            bucket("0.025", 0),
+            bucket("0.05", 1),
+            bucket("0.1", 1),
+            bucket("0.25", 1),
+            bucket("0.5", 2),
+            bucket("1", 2),
+            bucket("2.5", 2),
+            bucket("5", 2),
+            bucket("10", 2),
+            bucket("+Inf", 3),
+        ];
+        expected.push(format!("{}_sum{{route=\"test_buckets\"}} 64.40625", name));
+        expected.push(format!("{}_count{{route=\"test_buckets\"}} 3", name));
+
+        assert!(lines("test_buckets") == expected);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let handler = |req: &mut Request| -> IronResult<Response> {
+            if req.url.path() == vec!["fail"] {
+                let error = io::Error::new(io::ErrorKind::Other, "failed");
+                Err(IronError::new(error, status::ServiceUnavailable))
+            } else {
+                Ok(Response::with((status::ImATeapot, "tea")))
+            }
+        };
+
+        let listening = Iron::new(instrument("test_instrument", handler))
+            .http("127.0.0.1:0")
+            .unwrap();
+        let url = format!("http://{}", listening.socket);
+
+        for path in &["ok", "ok", "fail"] {
+            reqwest::get(&format!("{}/{}", url, path)).unwrap();
+        }
+
+        let lines = lines("test_instrument");
+        assert!(lines.contains(
+            &"importer_http_requests_total{handler=\"test_instrument\",status=\"418\"} 2"
+                .to_string()
+        ));
+        assert!(lines.contains(
+            &"importer_http_requests_total{handler=\"test_instrument\",status=\"503\"} 1"
+                .to_string()
+        ));
+        assert!(lines.contains(
+            &"importer_http_request_duration_seconds_count{handler=\"test_instrument\"} 3"
+                .to_string()
+        ));
+    }
+}
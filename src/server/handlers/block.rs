@@ -0,0 +1,98 @@
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use super::errors;
+use crate::metrics;
+use crate::storage::{block_by_date, block_by_hash, block_by_height};
+use crate::types::Error;
+use crate::Config;
+
+use cardano::block::types::HeaderHash;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Looks up an applied block by hash, by height, or by epoch and slot.
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        let by_height = Handler::new(self.config.clone());
+        let by_date = Handler::new(self.config.clone());
+
+        router
+            .get("/block/:hash", metrics::instrument("block", self), "block")
+            .get(
+                "/block/height/:height",
+                metrics::instrument("block", by_height),
+                "blockbyheight",
+            )
+            .get(
+                "/block/date/:epoch/:slot",
+                metrics::instrument("block", by_date),
+                "blockbydate",
+            )
+    }
+}
+
+fn invalid(name: &str, value: &str) -> Response {
+    errors::bad_request(
+        "invalid_parameter",
+        format!("Invalid {} {}", name, value),
+        Some(json!({ name: value })),
+    )
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let params = req.extensions.get::<router::Router>().unwrap();
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let block = if let Some(hash_str) = params.find("hash") {
+            match HeaderHash::from_str(hash_str) {
+                Ok(hash) => block_by_hash(&conn, &hash)?,
+                Err(_) => return Ok(invalid("hash", hash_str)),
+            }
+        } else if let Some(height_str) = params.find("height") {
+            match height_str.parse::<i64>() {
+                Ok(height) => block_by_height(&conn, height)?,
+                Err(_) => return Ok(invalid("height", height_str)),
+            }
+        } else {
+            let epoch_str = params.find("epoch").unwrap();
+            let slot_str = params.find("slot").unwrap();
+
+            let epoch = match epoch_str.parse::<i64>() {
+                Ok(epoch) => epoch,
+                Err(_) => return Ok(invalid("epoch", epoch_str)),
+            };
+
+            // the boundary block has no slot
+            let slot = match slot_str {
+                "boundary" => None,
+                _ => match slot_str.parse::<i64>() {
+                    Ok(slot) => Some(slot),
+                    Err(_) => return Ok(invalid("slot", slot_str)),
+                },
+            };
+
+            block_by_date(&conn, epoch, slot)?
+        };
+
+        let serialized = serde_json::to_string(&block).unwrap();
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
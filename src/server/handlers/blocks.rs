@@ -0,0 +1,103 @@
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use super::errors;
+use super::pagination;
+use crate::metrics;
+use crate::storage::{epoch_blocks, latest_blocks};
+use crate::types::Error;
+use crate::Config;
+
+use serde_json::json;
+use std::sync::Arc;
+
+const DEFAULT_LATEST: u32 = 10;
+const MAX_LATEST: u32 = 100;
+
+/// Lists the latest applied blocks, or a page of the applied blocks of an
+/// epoch.
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        let of_epoch = Handler::new(self.config.clone());
+
+        router
+            .get("/blocks/latest", metrics::instrument("blocks", self), "latestblocks")
+            .get(
+                "/epoch/:epoch/blocks",
+                metrics::instrument("blocks", of_epoch),
+                "epochblocks",
+            )
+    }
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let epoch = req
+            .extensions
+            .get::<router::Router>()
+            .unwrap()
+            .find("epoch")
+            .map(|epoch_str| epoch_str.parse::<i64>().map_err(|_| epoch_str.to_string()));
+
+        let (blocks, next) = match epoch {
+            Some(Ok(epoch)) => {
+                let page = match pagination::page(req) {
+                    Ok(page) => page,
+                    Err(e) => return Ok(errors::bad_request("invalid_parameter", e, None)),
+                };
+
+                let conn = self.config.pool.get().map_err(Error::from)?;
+                epoch_blocks(&conn, epoch, &page)?
+            }
+            Some(Err(epoch_str)) => {
+                return Ok(errors::bad_request(
+                    "invalid_parameter",
+                    format!("Invalid epoch {}", epoch_str),
+                    Some(json!({ "epoch": epoch_str })),
+                ))
+            }
+            None => {
+                let limit = req
+                    .url
+                    .as_ref()
+                    .query_pairs()
+                    .find(|(key, _)| key == "limit")
+                    .map(|(_, value)| value.into_owned());
+
+                let limit = match limit.as_ref().map(|limit| limit.parse::<u32>()) {
+                    None => DEFAULT_LATEST,
+                    Some(Ok(limit)) if limit > 0 && limit <= MAX_LATEST => limit,
+                    Some(_) => {
+                        return Ok(errors::bad_request(
+                            "invalid_parameter",
+                            format!("The limit has to be between 1 and {}", MAX_LATEST),
+                            Some(json!({ "limit": limit })),
+                        ))
+                    }
+                };
+
+                let conn = self.config.pool.get().map_err(Error::from)?;
+                (latest_blocks(&conn, limit)?, None)
+            }
+        };
+
+        let serialized = serde_json::to_string(&blocks).unwrap();
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+        pagination::set_next_cursor(&mut response, next);
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,45 @@
+use iron::response::Response;
+use iron::status::Status;
+use iron::{status, IronError};
+use serde_json::Value;
+
+use crate::types::{ApiError, Error};
+
+/// A response with the error schema shared by all the handlers.
+pub fn response(status: Status, code: &str, message: String, details: Option<Value>) -> Response {
+    let body = ApiError {
+        code: code.to_string(),
+        message,
+        details,
+    };
+
+    let mut response = Response::with((status, serde_json::to_string(&body).unwrap()));
+    response.headers.set(iron::headers::ContentType::json());
+    response
+}
+
+pub fn bad_request(code: &str, message: String, details: Option<Value>) -> Response {
+    response(status::BadRequest, code, message, details)
+}
+
+/// Lets handlers fail with `?`: missing records are answered with a 404, a
+/// lack of database connections with a 503, and anything else with a 500.
+impl From<Error> for IronError {
+    fn from(error: Error) -> IronError {
+        let (status, code) = match error {
+            Error::NotFound(_) => (status::NotFound, "not_found"),
+            Error::PoolError(_) => (status::ServiceUnavailable, "unavailable"),
+            _ => (status::InternalServerError, "internal_error"),
+        };
+
+        if status == status::InternalServerError {
+            error!("{}", error);
+        }
+
+        let body = response(status, code, format!("{}", error), None);
+        IronError {
+            error: Box::new(error),
+            response: body,
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use iron::headers::ContentType;
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use crate::metrics;
+use crate::Config;
+
+use std::sync::Arc;
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        router.get("/metrics", self, "metrics")
+    }
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let pool = self.config.pool.state();
+        metrics::set(metrics::POOL_CONNECTIONS, &[], f64::from(pool.connections));
+        metrics::set(metrics::POOL_IDLE_CONNECTIONS, &[], f64::from(pool.idle_connections));
+        metrics::set(metrics::POOL_MAX_SIZE, &[], f64::from(self.config.pool.max_size()));
+
+        let mut response = Response::with((status::Ok, metrics::render()));
+        response.headers.set(ContentType("text/plain; version=0.0.4".parse().unwrap()));
+
+        Ok(response)
+    }
+}
@@ -1,2 +1,16 @@
 pub mod txsbyaddress;
-pub mod tx;
\ No newline at end of file
+pub mod txsbyaddresses;
+pub mod tx;
+pub mod rawtx;
+pub mod submit;
+pub mod utxos;
+pub mod balance;
+pub mod summary;
+pub mod block;
+pub mod blocks;
+pub mod status;
+pub mod stream;
+pub mod webhooks;
+pub mod metrics;
+pub mod pagination;
+pub mod errors;
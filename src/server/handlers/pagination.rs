@@ -0,0 +1,43 @@
+use iron::request::Request;
+use iron::response::Response;
+
+use crate::types::{Cursor, Order, Page, MAX_PAGE_LIMIT};
+
+/// Header holding the cursor of the next page, missing on the last one.
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+/// Read the `limit`, `cursor` and `order` query parameters of the request.
+pub fn page(req: &Request) -> Result<Page, String> {
+    let mut page = Page::default();
+
+    for (key, value) in req.url.as_ref().query_pairs() {
+        match key.as_ref() {
+            "limit" => match value.parse::<u32>() {
+                Ok(limit) if limit > 0 && limit <= MAX_PAGE_LIMIT => page.limit = limit,
+                _ => {
+                    return Err(format!(
+                        "Invalid limit {}, it has to be between 1 and {}",
+                        value, MAX_PAGE_LIMIT
+                    ))
+                }
+            },
+            "cursor" => page.cursor = Some(value.parse::<Cursor>()?),
+            "order" => match value.as_ref() {
+                "asc" => page.order = Order::Asc,
+                "desc" => page.order = Order::Desc,
+                _ => return Err(format!("Invalid order {}", value)),
+            },
+            _ => (),
+        }
+    }
+
+    Ok(page)
+}
+
+pub fn set_next_cursor(response: &mut Response, cursor: Option<Cursor>) {
+    if let Some(cursor) = cursor {
+        response
+            .headers
+            .set_raw(NEXT_CURSOR_HEADER, vec![format!("{}", cursor).into_bytes()]);
+    }
+}
@@ -0,0 +1,137 @@
+use iron::mime::Mime;
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use super::errors;
+use crate::storage::raw_transaction;
+use crate::types::{RawTransaction, Witness};
+use crate::metrics;
+use crate::types::Error;
+use crate::Config;
+
+use cardano::tx::{TxAux, TxId, TxInWitness};
+use cardano::util::hex;
+use cbor_event::de::Deserializer;
+use serde_json::json;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        router.get(
+            "/transaction/:tx/raw",
+            metrics::instrument("rawtransaction", self),
+            "rawtransaction",
+        )
+    }
+}
+
+fn decode(txid: &str, raw: &[u8]) -> cbor_event::Result<RawTransaction> {
+    let tx_aux: TxAux = Deserializer::from(Cursor::new(raw)).deserialize()?;
+
+    let witnesses = tx_aux
+        .witness
+        .iter()
+        .map(|witness| match witness {
+            TxInWitness::PkWitness(key, _) => Witness::PkWitness {
+                public_key: format!("{}", key),
+            },
+            TxInWitness::RedeemWitness(key, _) => Witness::RedeemWitness {
+                public_key: format!("{}", key),
+            },
+            TxInWitness::ScriptWitness(..) => Witness::ScriptWitness,
+        })
+        .collect();
+
+    Ok(RawTransaction {
+        txid: txid.to_string(),
+        cbor: hex::encode(raw),
+        witnesses,
+    })
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let params = req.extensions.get::<router::Router>().unwrap();
+        let txid_str = params.find("tx").unwrap();
+
+        if TxId::from_str(txid_str).is_err() {
+            return Ok(errors::bad_request(
+                "invalid_txid",
+                format!("Invalid transaction id {}", txid_str),
+                Some(json!({ "txid": txid_str })),
+            ));
+        }
+
+        let format = req
+            .url
+            .as_ref()
+            .query_pairs()
+            .find(|(key, _)| key == "format")
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_else(|| "cbor".to_string());
+
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let raw = match raw_transaction(&conn, txid_str) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => {
+                return Ok(errors::response(
+                    status::NotFound,
+                    "unknown_encoding",
+                    format!("The encoding of transaction {} is unknown", txid_str),
+                    Some(json!({ "txid": txid_str })),
+                ))
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(Error::NotFound(format!("transaction {}", txid_str)).into())
+            }
+            Err(e) => return Err(Error::from(e).into()),
+        };
+
+        match format.as_str() {
+            "cbor" => {
+                let mime: Mime = "application/cbor".parse().unwrap();
+                Ok(Response::with((status::Ok, mime, raw)))
+            }
+            "hex" => Ok(Response::with((status::Ok, hex::encode(&raw)))),
+            "json" => {
+                let decoded = match decode(txid_str, &raw) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        error!("Could not decode transaction {}: {:?}", txid_str, e);
+                        return Err(Error::DecodeError(
+                            format!("transaction {}", txid_str),
+                            format!("{}", e),
+                        )
+                        .into());
+                    }
+                };
+
+                let serialized = serde_json::to_string(&decoded).unwrap();
+
+                let mut response = Response::with((status::Ok, serialized));
+                response.headers.set(iron::headers::ContentType::json());
+
+                Ok(response)
+            }
+            _ => Ok(errors::bad_request(
+                "invalid_parameter",
+                format!("Invalid format {}, expected cbor, hex or json", format),
+                Some(json!({ "format": format })),
+            )),
+        }
+    }
+}
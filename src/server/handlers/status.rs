@@ -0,0 +1,104 @@
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use crate::storage::{block_date, last_applied_block, last_applied_height};
+use crate::types::{BlockStatus, ErrorStatus, Status};
+use crate::metrics;
+use crate::types::Error;
+use crate::Config;
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        router.get("/status", metrics::instrument("status", self), "status")
+    }
+
+    /// Slot count since the start of the chain, boundary blocks sharing the
+    /// first slot of their epoch.
+    fn absolute_slot(&self, epoch: i64, slot: Option<i64>) -> u64 {
+        let epoch_slots = 10 * self.config.epoch_stability_depth as u64;
+        epoch as u64 * epoch_slots + slot.unwrap_or(0) as u64
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let last_applied = last_applied_block(&conn)?;
+        let last_applied_date = match last_applied {
+            Some(ref hash) => block_date(&conn, hash).map_err(Error::from)?,
+            None => None,
+        };
+        let last_applied_height = last_applied_height(&conn).map_err(Error::from)?;
+        drop(conn);
+
+        // the tip is the one the sync loop last read, so a probe never waits
+        // on the bridge
+        let sync_state = self.config.sync_state.lock().unwrap();
+
+        let tip_date = sync_state
+            .tip
+            .as_ref()
+            .and_then(|tip| tip.epoch.map(|epoch| (epoch, tip.slot)));
+
+        let blocks_behind = match (sync_state.tip_height, last_applied_height) {
+            (Some(tip), Some(height)) => Some((tip - height).max(0) as u64),
+            _ => None,
+        };
+
+        let slots_behind = match (tip_date, last_applied_date) {
+            (Some((tip_epoch, tip_slot)), Some((epoch, slot))) => Some(
+                self.absolute_slot(tip_epoch, tip_slot)
+                    .saturating_sub(self.absolute_slot(epoch, slot)),
+            ),
+            _ => None,
+        };
+
+        let sync_status = Status {
+            tip: sync_state.tip.clone(),
+            last_applied: last_applied.map(|hash| BlockStatus {
+                hash: format!("{}", hash),
+                epoch: last_applied_date.map(|(epoch, _)| epoch),
+                slot: last_applied_date.and_then(|(_, slot)| slot),
+            }),
+            blocks_behind,
+            slots_behind,
+            last_sync: sync_state.last_success.map(seconds),
+            last_error: sync_state
+                .last_error
+                .as_ref()
+                .map(|(time, message)| ErrorStatus {
+                    time: seconds(*time),
+                    message: message.clone(),
+                }),
+            bridge_error: sync_state.bridge_error.clone(),
+        };
+
+        let serialized = serde_json::to_string(&sync_status).unwrap();
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
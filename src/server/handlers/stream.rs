@@ -0,0 +1,300 @@
+use iron::method::Method;
+use iron::mime::Mime;
+use iron::request::Request;
+use iron::response::{Response, WriteBody};
+use iron::status;
+use iron::IronResult;
+use router::Router;
+use serde::{Deserialize, Serialize};
+
+use super::errors;
+use crate::events::{Event, Subscription};
+use crate::metrics;
+use crate::storage::{applied_after, block_activity};
+use crate::types::{Activity, BlockActivity, Error};
+use crate::Config;
+
+use cardano::address::ExtendedAddr;
+use cardano::block::types::HeaderHash;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The addresses are matched in memory, this only bounds the request size.
+const MAX_ADDRESSES: usize = 10_000;
+
+/// Time without events after which a comment is sent, so proxies and clients
+/// don't take the connection for dead.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Header sent by reconnecting `EventSource`s with the id of the last event.
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+#[derive(Deserialize)]
+struct Body {
+    addresses: Vec<String>,
+    from: Option<String>,
+}
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        let post = Handler::new(self.config.clone());
+
+        router
+            .get("/stream", metrics::instrument("stream", self), "stream")
+            .post("/stream", metrics::instrument("stream", post), "streamaddresses")
+    }
+}
+
+/// The requested addresses and the block to resume after. The addresses are
+/// repeated `address` query parameters, or the body of a POST for large sets.
+fn read_request(req: &mut Request) -> Result<(Vec<String>, Option<String>), Response> {
+    let (addresses, mut from) = if req.method == Method::Post {
+        match serde_json::from_reader::<_, Body>(&mut req.body) {
+            Ok(body) => (body.addresses, body.from),
+            Err(e) => {
+                return Err(errors::bad_request(
+                    "invalid_body",
+                    format!("Invalid body: {}", e),
+                    None,
+                ))
+            }
+        }
+    } else {
+        let mut addresses = vec![];
+        let mut from = None;
+        for (key, value) in req.url.as_ref().query_pairs() {
+            match key.as_ref() {
+                "address" => addresses.push(value.into_owned()),
+                "from" => from = Some(value.into_owned()),
+                _ => (),
+            }
+        }
+        (addresses, from)
+    };
+
+    // a reconnection resumes after the last event received, whatever the
+    // request first asked for
+    if let Some(raw) = req.headers.get_raw(LAST_EVENT_ID_HEADER) {
+        if let Some(last) = raw.first() {
+            from = Some(String::from_utf8_lossy(last).into_owned());
+        }
+    }
+
+    Ok((addresses, from))
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (requested, from) = match read_request(req) {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
+
+        if requested.is_empty() {
+            return Ok(errors::bad_request(
+                "invalid_parameter",
+                "At least one address has to be requested".to_string(),
+                None,
+            ));
+        }
+
+        if requested.len() > MAX_ADDRESSES {
+            return Ok(errors::bad_request(
+                "too_many_addresses",
+                format!("At most {} addresses can be requested", MAX_ADDRESSES),
+                Some(json!({ "max": MAX_ADDRESSES, "requested": requested.len() })),
+            ));
+        }
+
+        let mut addresses = HashSet::new();
+        for address_str in requested.iter() {
+            match ExtendedAddr::from_str(address_str) {
+                Ok(addr) => addresses.insert(format!("{}", addr)),
+                Err(_) => {
+                    return Ok(errors::bad_request(
+                        "invalid_address",
+                        format!("Invalid address {}", address_str),
+                        Some(json!({ "address": address_str })),
+                    ))
+                }
+            };
+        }
+
+        let from = match from.map(|from| HeaderHash::from_str(&from).map_err(|_| from)) {
+            Some(Ok(from)) => Some(from),
+            Some(Err(from)) => {
+                return Ok(errors::bad_request(
+                    "invalid_parameter",
+                    format!("Invalid block hash {}", from),
+                    Some(json!({ "from": from })),
+                ))
+            }
+            None => None,
+        };
+
+        // subscribed before reading what to replay, so no block is missed in
+        // between
+        let subscription = match self.config.events.subscribe() {
+            Some(subscription) => subscription,
+            None => {
+                return Ok(errors::response(
+                    status::ServiceUnavailable,
+                    "unavailable",
+                    format!(
+                        "At most {} streams can be open",
+                        self.config.stream_max_clients
+                    ),
+                    None,
+                ))
+            }
+        };
+
+        let replay = match from {
+            Some(from) => {
+                let conn = self.config.pool.get().map_err(Error::from)?;
+
+                match applied_after(&conn, &from)? {
+                    Some(replay) => replay,
+                    None => {
+                        return Ok(errors::response(
+                            status::NotFound,
+                            "unknown_block",
+                            format!(
+                                "Block {} is not in the chain, it may have been rolled back",
+                                from
+                            ),
+                            Some(json!({ "from": format!("{}", from) })),
+                        ))
+                    }
+                }
+            }
+            None => vec![],
+        };
+
+        let stream = Stream {
+            config: self.config.clone(),
+            addresses,
+            replay,
+            subscription,
+        };
+
+        let mime: Mime = "text/event-stream".parse().unwrap();
+        let mut response = Response::with((status::Ok, mime));
+        response.headers.set(iron::headers::CacheControl(vec![
+            iron::headers::CacheDirective::NoCache,
+        ]));
+        response.body = Some(Box::new(stream));
+
+        Ok(response)
+    }
+}
+
+/// The server-sent events of a client: the replayed blocks, then the live
+/// ones, until the client goes away or falls behind.
+struct Stream {
+    config: Arc<Config>,
+    addresses: HashSet<String>,
+    replay: Vec<HeaderHash>,
+    subscription: Subscription,
+}
+
+fn io_error<E: std::fmt::Display>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", error))
+}
+
+fn write_event<T: Serialize>(
+    res: &mut dyn Write,
+    event: &str,
+    id: &str,
+    data: &T,
+) -> io::Result<()> {
+    let data = serde_json::to_string(data).map_err(io_error)?;
+    write!(res, "event: {}\nid: {}\ndata: {}\n\n", event, id, data)
+}
+
+impl Stream {
+    /// Send the transactions of `activity` touching the addresses, only moving
+    /// the client's last event id forward if there are none.
+    fn send_block(&self, res: &mut dyn Write, activity: BlockActivity) -> io::Result<()> {
+        let transactions: Vec<Activity> = activity
+            .transactions
+            .into_iter()
+            .filter_map(|tx| {
+                let addresses: Vec<String> = tx
+                    .addresses
+                    .into_iter()
+                    .filter(|address| self.addresses.contains(address))
+                    .collect();
+
+                if addresses.is_empty() {
+                    None
+                } else {
+                    Some(Activity {
+                        txid: tx.txid,
+                        addresses,
+                    })
+                }
+            })
+            .collect();
+
+        if transactions.is_empty() {
+            return write!(res, "id: {}\n\n", activity.block);
+        }
+
+        let id = activity.block.clone();
+        write_event(
+            res,
+            "transactions",
+            &id,
+            &BlockActivity {
+                transactions,
+                ..activity
+            },
+        )
+    }
+}
+
+impl WriteBody for Stream {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut replayed = HashSet::new();
+
+        if !self.replay.is_empty() {
+            let conn = self.config.pool.get().map_err(io_error)?;
+
+            for hash in self.replay.iter() {
+                let activity = block_activity(&conn, hash).map_err(io_error)?;
+                replayed.insert(activity.block.clone());
+                self.send_block(res, activity)?;
+            }
+        }
+        res.flush()?;
+
+        loop {
+            match self.subscription.next(KEEPALIVE) {
+                // already sent by the replay
+                Ok(Event::Block(ref activity)) if replayed.remove(&activity.block) => continue,
+                Ok(Event::Block(activity)) => self.send_block(res, activity)?,
+                Ok(Event::Rollback(rollback)) => {
+                    write_event(res, "rollback", &rollback.ancestor, &rollback)?
+                }
+                Err(RecvTimeoutError::Timeout) => res.write_all(b": keepalive\n\n")?,
+                // dropped for falling behind, the client resumes on reconnect
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            res.flush()?;
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use iron::method::Method;
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use super::errors;
+use crate::http_bridge::HttpBridgeApi;
+use crate::metrics;
+use crate::storage::{output_spent, record_pending, submission};
+use crate::types::Error;
+use crate::Config;
+
+use cardano::tx::{TxAux, TxId};
+use cardano::util::hex;
+use cbor_event::de::Deserializer;
+use serde_json::json;
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Far above the size of a Byron transaction, this only bounds the request.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// `POST /transaction` sends a signed transaction to the network through the
+/// bridge, and `GET /transaction/:tx/status` follows it until it is in a block
+/// or expires.
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        let status = Handler::new(self.config.clone());
+
+        router
+            .post(
+                "/transaction",
+                metrics::instrument("submittransaction", self),
+                "submittransaction",
+            )
+            .get(
+                "/transaction/:tx/status",
+                metrics::instrument("transactionstatus", status),
+                "transactionstatus",
+            )
+    }
+
+    fn status(&self, req: &mut Request) -> IronResult<Response> {
+        let params = req.extensions.get::<router::Router>().unwrap();
+        let txid_str = params.find("tx").unwrap();
+
+        if TxId::from_str(txid_str).is_err() {
+            return Ok(errors::bad_request(
+                "invalid_txid",
+                format!("Invalid transaction id {}", txid_str),
+                Some(json!({ "txid": txid_str })),
+            ));
+        }
+
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let submission = submission(&conn, txid_str, self.config.pending_timeout)?;
+
+        let mut response =
+            Response::with((status::Ok, serde_json::to_string(&submission).unwrap()));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
+
+/// The cbor of the transaction: the body itself when it is sent as
+/// `application/cbor`, its hex encoding otherwise.
+fn read_transaction(req: &mut Request) -> Result<Vec<u8>, Response> {
+    let mut body = vec![];
+    if let Err(e) = req
+        .body
+        .by_ref()
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_end(&mut body)
+    {
+        return Err(errors::bad_request(
+            "invalid_body",
+            format!("Invalid body: {}", e),
+            None,
+        ));
+    }
+
+    if body.len() as u64 > MAX_BODY_SIZE {
+        return Err(errors::bad_request(
+            "invalid_body",
+            format!("The body can't be larger than {} bytes", MAX_BODY_SIZE),
+            Some(json!({ "max": MAX_BODY_SIZE })),
+        ));
+    }
+
+    let is_cbor = req
+        .headers
+        .get_raw("Content-Type")
+        .and_then(|values| values.first())
+        .map_or(false, |value| value.starts_with(b"application/cbor"));
+
+    if is_cbor {
+        return Ok(body);
+    }
+
+    let text = String::from_utf8_lossy(&body);
+    hex::decode(text.trim()).map_err(|_| {
+        errors::bad_request(
+            "invalid_body",
+            "The body must be the hex of the transaction, or its cbor sent as application/cbor"
+                .to_string(),
+            None,
+        )
+    })
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if req.method != Method::Post {
+            return self.status(req);
+        }
+
+        let raw = match read_transaction(req) {
+            Ok(raw) => raw,
+            Err(response) => return Ok(response),
+        };
+
+        let tx_aux: TxAux = match Deserializer::from(Cursor::new(&raw)).deserialize() {
+            Ok(tx_aux) => tx_aux,
+            Err(e) => {
+                return Ok(errors::bad_request(
+                    "invalid_transaction",
+                    format!("Could not decode the signed transaction: {}", e),
+                    None,
+                ))
+            }
+        };
+        let txid = format!("{}", tx_aux.tx.id());
+
+        // one witness per input, signing the transaction for this network
+        let protocol_magic = self.config.protocol_magic;
+        if tx_aux.witness.len() != tx_aux.tx.inputs.len()
+            || !tx_aux
+                .witness
+                .iter()
+                .all(|witness| witness.verify_tx(protocol_magic, &tx_aux.tx))
+        {
+            return Ok(errors::bad_request(
+                "invalid_witness",
+                format!(
+                    "The witnesses of transaction {} don't sign it for this network",
+                    txid
+                ),
+                Some(json!({ "txid": txid })),
+            ));
+        }
+
+        // released before the bridge is called, which can take its timeout
+        {
+            let conn = self.config.pool.get().map_err(Error::from)?;
+
+            for input in tx_aux.tx.inputs.iter() {
+                let pointer = format!("{}@{}", input.id, input.index);
+
+                match output_spent(&conn, input).map_err(Error::from)? {
+                    Some(false) => (),
+                    Some(true) => {
+                        return Ok(errors::response(
+                            status::Conflict,
+                            "spent_input",
+                            format!("Input {} is already spent", pointer),
+                            Some(json!({ "txid": txid, "input": pointer })),
+                        ))
+                    }
+                    None => {
+                        return Ok(errors::bad_request(
+                            "unknown_input",
+                            format!("Input {} is not indexed", pointer),
+                            Some(json!({ "txid": txid, "input": pointer })),
+                        ))
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self.config.bridge.send_transaction(&raw) {
+            warn!("The bridge refused transaction {}: {}", txid, e);
+            return Ok(errors::response(
+                status::BadGateway,
+                "bridge_error",
+                format!("The bridge could not submit transaction {}: {}", txid, e),
+                Some(json!({ "txid": txid })),
+            ));
+        }
+
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        record_pending(&conn, &txid).map_err(Error::from)?;
+        info!("Transaction {} submitted", txid);
+
+        let submission = submission(&conn, &txid, self.config.pending_timeout)?;
+
+        let mut response = Response::with((
+            status::Accepted,
+            serde_json::to_string(&submission).unwrap(),
+        ));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
@@ -4,9 +4,15 @@ use iron::status;
 use iron::IronResult;
 use router::Router;
 
+use super::errors;
 use crate::storage::transaction;
+use crate::metrics;
+use crate::types::Error;
 use crate::Config;
 
+use cardano::tx::TxId;
+use serde_json::json;
+use std::str::FromStr;
 use std::sync::Arc;
 
 pub struct Handler {
@@ -19,7 +25,7 @@ impl Handler {
     }
 
     pub fn route(self, router: &mut Router) -> &mut Router {
-        router.get("/transaction/:tx", self, "transaction")
+        router.get("/transaction/:tx", metrics::instrument("transaction", self), "transaction")
     }
 }
 
@@ -28,14 +34,17 @@ impl iron::Handler for Handler {
         let params = req.extensions.get::<router::Router>().unwrap();
         let txid_str = params.find("tx").unwrap();
 
-        let conn = match self.config.pool.get() {
-            Ok(c) => c,
-            Err(_) => {
-                panic!("Couldn't get a connection to the database");
-            }
-        };
+        if TxId::from_str(txid_str).is_err() {
+            return Ok(errors::bad_request(
+                "invalid_txid",
+                format!("Invalid transaction id {}", txid_str),
+                Some(json!({ "txid": txid_str })),
+            ));
+        }
 
-        let transaction = transaction(&conn, txid_str.to_string()).unwrap();
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let transaction = transaction(&conn, txid_str.to_string())?;
 
         let serialized = serde_json::to_string(&transaction).unwrap();
 
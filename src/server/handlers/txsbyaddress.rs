@@ -4,10 +4,15 @@ use iron::status;
 use iron::IronResult;
 use router::Router;
 
+use super::errors;
+use super::pagination;
 use crate::storage::transactions_of;
+use crate::metrics;
+use crate::types::Error;
 use crate::Config;
 
 use cardano::address::ExtendedAddr;
+use serde_json::json;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -21,7 +26,11 @@ impl Handler {
     }
 
     pub fn route(self, router: &mut Router) -> &mut Router {
-        router.get("/transactions/:address", self, "transactionsbyaddress")
+        router.get(
+            "/transactions/:address",
+            metrics::instrument("transactionsbyaddress", self),
+            "transactionsbyaddress",
+        )
     }
 }
 
@@ -32,22 +41,33 @@ impl iron::Handler for Handler {
 
         let address = match ExtendedAddr::from_str(&address_str) {
             Ok(addr) => addr,
-            Err(_) => return Ok(Response::with((status::BadRequest, "Invalid address"))),
-        };
-
-        let conn = match self.config.pool.get() {
-            Ok(c) => c,
             Err(_) => {
-                panic!("Couldn't get a connection to the database");
+                return Ok(errors::bad_request(
+                    "invalid_address",
+                    format!("Invalid address {}", address_str),
+                    Some(json!({ "address": address_str })),
+                ))
             }
         };
 
-        let transactions = transactions_of(&conn, address).unwrap();
+        let page = match pagination::page(req) {
+            Ok(page) => page,
+            Err(e) => return Ok(errors::bad_request("invalid_parameter", e, None)),
+        };
+
+        let mut conn = self.config.pool.get().map_err(Error::from)?;
+
+        // a single read transaction keeps the page consistent while blocks
+        // are being applied
+        let transaction = conn.transaction().map_err(Error::from)?;
+
+        let (transactions, next) = transactions_of(&transaction, &[address], &page)?;
 
         let serialized = serde_json::to_string(&transactions).unwrap();
 
         let mut response = Response::with((status::Ok, serialized));
         response.headers.set(iron::headers::ContentType::json());
+        pagination::set_next_cursor(&mut response, next);
 
         Ok(response)
     }
@@ -0,0 +1,121 @@
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+use serde::Deserialize;
+
+use super::errors;
+use super::pagination;
+use crate::storage::{addresses_of, transactions_of};
+use crate::types::MatchedTransaction;
+use crate::metrics;
+use crate::types::Error;
+use crate::Config;
+
+use cardano::address::ExtendedAddr;
+use std::collections::BTreeSet;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Each address is a parameter of the history query, which sqlite caps at 999.
+const MAX_ADDRESSES: usize = 500;
+
+#[derive(Deserialize)]
+struct Body {
+    addresses: Vec<String>,
+}
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        router.post(
+            "/transactions",
+            metrics::instrument("transactionsbyaddresses", self),
+            "transactionsbyaddresses",
+        )
+    }
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let body: Body = match serde_json::from_reader(&mut req.body) {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(errors::bad_request(
+                    "invalid_body",
+                    format!("Invalid body: {}", e),
+                    None,
+                ))
+            }
+        };
+
+        let requested: BTreeSet<String> = body.addresses.into_iter().collect();
+
+        if requested.len() > MAX_ADDRESSES {
+            return Ok(errors::bad_request(
+                "too_many_addresses",
+                format!("At most {} addresses can be requested", MAX_ADDRESSES),
+                Some(json!({ "max": MAX_ADDRESSES, "requested": requested.len() })),
+            ));
+        }
+
+        let mut addresses = vec![];
+        for address_str in requested.iter() {
+            match ExtendedAddr::from_str(address_str) {
+                Ok(addr) => addresses.push(addr),
+                Err(_) => {
+                    return Ok(errors::bad_request(
+                        "invalid_address",
+                        format!("Invalid address {}", address_str),
+                        Some(json!({ "address": address_str })),
+                    ))
+                }
+            };
+        }
+
+        let page = match pagination::page(req) {
+            Ok(page) => page,
+            Err(e) => return Ok(errors::bad_request("invalid_parameter", e, None)),
+        };
+
+        let mut conn = self.config.pool.get().map_err(Error::from)?;
+
+        // a single read transaction keeps the page consistent while blocks
+        // are being applied
+        let transaction = conn.transaction().map_err(Error::from)?;
+
+        let (transactions, next) = transactions_of(&transaction, &addresses, &page)?;
+
+        let transactions = transactions
+            .into_iter()
+            .map(|tx| {
+                let addresses = addresses_of(&transaction, &tx.txid)?
+                    .into_iter()
+                    .filter(|address| requested.contains(address))
+                    .collect();
+
+                Ok(MatchedTransaction {
+                    transaction: tx,
+                    addresses,
+                })
+            })
+            .collect::<Result<Vec<MatchedTransaction>, Error>>()?;
+
+        let serialized = serde_json::to_string(&transactions).unwrap();
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+        pagination::set_next_cursor(&mut response, next);
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,59 @@
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+
+use super::errors;
+use crate::storage::utxos_of;
+use crate::metrics;
+use crate::types::Error;
+use crate::Config;
+
+use cardano::address::ExtendedAddr;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        router.get("/utxos/:address", metrics::instrument("utxos", self), "utxos")
+    }
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let params = req.extensions.get::<router::Router>().unwrap();
+        let address_str = params.find("address").unwrap();
+
+        let address = match ExtendedAddr::from_str(&address_str) {
+            Ok(addr) => addr,
+            Err(_) => {
+                return Ok(errors::bad_request(
+                    "invalid_address",
+                    format!("Invalid address {}", address_str),
+                    Some(json!({ "address": address_str })),
+                ))
+            }
+        };
+
+        let conn = self.config.pool.get().map_err(Error::from)?;
+
+        let utxos = utxos_of(&conn, address).map_err(Error::from)?;
+
+        let serialized = serde_json::to_string(&utxos).unwrap();
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
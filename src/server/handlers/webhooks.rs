@@ -0,0 +1,231 @@
+use iron::method::Method;
+use iron::request::Request;
+use iron::response::Response;
+use iron::status;
+use iron::IronResult;
+use router::Router;
+use serde::Deserialize;
+
+use super::errors;
+use crate::metrics;
+use crate::storage::{delete_webhook, insert_webhook, webhook, webhooks};
+use crate::types::Error;
+use crate::Config;
+
+use cardano::address::ExtendedAddr;
+use cryptoxide::util::fixed_time_eq;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const MAX_ADDRESSES: usize = 10_000;
+
+#[derive(Deserialize)]
+struct Body {
+    url: String,
+    addresses: Vec<String>,
+    confirmations: u32,
+    // key of the HMAC signing the payloads
+    secret: String,
+}
+
+/// The admin api managing the webhooks: `POST /admin/webhooks` registers one,
+/// `GET` lists them or shows one, `DELETE` drops one.
+pub struct Handler {
+    config: Arc<Config>,
+}
+
+impl Handler {
+    pub fn new(config: Arc<Config>) -> Self {
+        Handler { config }
+    }
+
+    pub fn route(self, router: &mut Router) -> &mut Router {
+        let list = Handler::new(self.config.clone());
+        let show = Handler::new(self.config.clone());
+        let delete = Handler::new(self.config.clone());
+
+        router
+            .post("/admin/webhooks", metrics::instrument("webhooks", self), "addwebhook")
+            .get("/admin/webhooks", metrics::instrument("webhooks", list), "webhooks")
+            .get("/admin/webhooks/:id", metrics::instrument("webhooks", show), "webhook")
+            .delete(
+                "/admin/webhooks/:id",
+                metrics::instrument("webhooks", delete),
+                "deletewebhook",
+            )
+    }
+
+    /// The error response if the request doesn't carry the admin token.
+    fn authorize(&self, req: &Request) -> Option<Response> {
+        let token = match self.config.admin_token {
+            Some(ref token) => token,
+            None => {
+                return Some(errors::response(
+                    status::Forbidden,
+                    "forbidden",
+                    "The admin api is disabled, set admin-token to enable it".to_string(),
+                    None,
+                ))
+            }
+        };
+
+        let expected = format!("Bearer {}", token);
+        let authorized = req
+            .headers
+            .get_raw("Authorization")
+            .and_then(|values| values.first())
+            .map_or(false, |value| {
+                value.len() == expected.len() && fixed_time_eq(value, expected.as_bytes())
+            });
+
+        if authorized {
+            None
+        } else {
+            Some(errors::response(
+                status::Unauthorized,
+                "unauthorized",
+                "The admin token is missing or wrong".to_string(),
+                None,
+            ))
+        }
+    }
+
+    fn add(&self, req: &mut Request) -> IronResult<Response> {
+        let body: Body = match serde_json::from_reader(&mut req.body) {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(errors::bad_request(
+                    "invalid_body",
+                    format!("Invalid body: {}", e),
+                    None,
+                ))
+            }
+        };
+
+        match reqwest::Url::parse(&body.url) {
+            Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => (),
+            _ => {
+                return Ok(errors::bad_request(
+                    "invalid_parameter",
+                    format!("Invalid url {}", body.url),
+                    Some(json!({ "url": body.url })),
+                ))
+            }
+        }
+
+        if body.confirmations == 0 {
+            return Ok(errors::bad_request(
+                "invalid_parameter",
+                "At least one confirmation is required".to_string(),
+                Some(json!({ "confirmations": body.confirmations })),
+            ));
+        }
+
+        if body.secret.is_empty() {
+            return Ok(errors::bad_request(
+                "invalid_parameter",
+                "The secret can't be empty".to_string(),
+                None,
+            ));
+        }
+
+        if body.addresses.is_empty() {
+            return Ok(errors::bad_request(
+                "invalid_parameter",
+                "At least one address has to be watched".to_string(),
+                None,
+            ));
+        }
+
+        if body.addresses.len() > MAX_ADDRESSES {
+            return Ok(errors::bad_request(
+                "too_many_addresses",
+                format!("At most {} addresses can be watched", MAX_ADDRESSES),
+                Some(json!({ "max": MAX_ADDRESSES, "requested": body.addresses.len() })),
+            ));
+        }
+
+        let mut addresses = vec![];
+        for address_str in body.addresses.iter() {
+            match ExtendedAddr::from_str(address_str) {
+                Ok(addr) => addresses.push(format!("{}", addr)),
+                Err(_) => {
+                    return Ok(errors::bad_request(
+                        "invalid_address",
+                        format!("Invalid address {}", address_str),
+                        Some(json!({ "address": address_str })),
+                    ))
+                }
+            };
+        }
+
+        let mut conn = self.config.pool.get().map_err(Error::from)?;
+        let transaction = conn.transaction().map_err(Error::from)?;
+
+        let webhook = insert_webhook(
+            &transaction,
+            &body.url,
+            &body.secret,
+            body.confirmations,
+            &addresses,
+        )?;
+        transaction.commit().map_err(Error::from)?;
+
+        info!("Webhook {} registered for {}", webhook.id, webhook.url);
+
+        let mut response = Response::with((
+            status::Created,
+            serde_json::to_string(&webhook).unwrap(),
+        ));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
+
+impl iron::Handler for Handler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if let Some(response) = self.authorize(req) {
+            return Ok(response);
+        }
+
+        if req.method == Method::Post {
+            return self.add(req);
+        }
+
+        let id = match req.extensions.get::<Router>().unwrap().find("id") {
+            None => None,
+            Some(id_str) => match id_str.parse::<i64>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    return Ok(errors::bad_request(
+                        "invalid_parameter",
+                        format!("Invalid webhook id {}", id_str),
+                        Some(json!({ "id": id_str })),
+                    ))
+                }
+            },
+        };
+
+        let mut conn = self.config.pool.get().map_err(Error::from)?;
+
+        let serialized = match (&req.method, id) {
+            (Method::Delete, Some(id)) => {
+                let transaction = conn.transaction().map_err(Error::from)?;
+                delete_webhook(&transaction, id)?;
+                transaction.commit().map_err(Error::from)?;
+
+                info!("Webhook {} deleted", id);
+                return Ok(Response::with(status::NoContent));
+            }
+            (_, Some(id)) => serde_json::to_string(&webhook(&conn, id)?).unwrap(),
+            (_, None) => serde_json::to_string(&webhooks(&conn)?).unwrap(),
+        };
+
+        let mut response = Response::with((status::Ok, serialized));
+        response.headers.set(iron::headers::ContentType::json());
+
+        Ok(response)
+    }
+}
@@ -5,7 +5,19 @@ use iron::Listening;
 mod handlers;
 
 use handlers::txsbyaddress;
+use handlers::txsbyaddresses;
 use handlers::tx;
+use handlers::rawtx;
+use handlers::submit;
+use handlers::utxos;
+use handlers::balance;
+use handlers::summary;
+use handlers::block;
+use handlers::blocks;
+use handlers::status;
+use handlers::stream;
+use handlers::webhooks;
+use handlers::metrics;
 use crate::Config;
 use log::info;
 use std::sync::Arc;
@@ -14,9 +26,24 @@ pub fn start_http_server(config: Arc<Config>) -> Listening {
     let mut router = Router::new();
 
     txsbyaddress::Handler::new(config.clone()).route(&mut router);
+    txsbyaddresses::Handler::new(config.clone()).route(&mut router);
     tx::Handler::new(config.clone()).route(&mut router);
+    rawtx::Handler::new(config.clone()).route(&mut router);
+    submit::Handler::new(config.clone()).route(&mut router);
+    utxos::Handler::new(config.clone()).route(&mut router);
+    balance::Handler::new(config.clone()).route(&mut router);
+    summary::Handler::new(config.clone()).route(&mut router);
+    block::Handler::new(config.clone()).route(&mut router);
+    blocks::Handler::new(config.clone()).route(&mut router);
+    status::Handler::new(config.clone()).route(&mut router);
+    metrics::Handler::new(config.clone()).route(&mut router);
+    stream::Handler::new(config.clone()).route(&mut router);
+    webhooks::Handler::new(config.clone()).route(&mut router);
     
     info!("listening to port {}", config.port);
-    Iron::new(router).http(format!("0.0.0.0:{}", config.port))
+    let mut iron = Iron::new(router);
+    // every open stream holds a thread, on top of the ones serving requests
+    iron.threads += config.stream_max_clients;
+    iron.http(format!("0.0.0.0:{}", config.port))
         .expect("start http server")
 }
\ No newline at end of file
@@ -2,14 +2,24 @@ use rusqlite::params;
 
 use cardano::address::ExtendedAddr;
 use cardano::block::chain_state::Utxos;
-use cardano::tx::Tx;
+use cardano::tx::{Tx, TxAux};
 use cardano::tx::{TxOut, TxoPointer};
 
 use cardano::block::block::Block;
+use cardano::block::date::BlockDate;
 
-use crate::types::{Input, Output, Transaction};
+use crate::types::{
+    Activity, AddressSummary, Balance, BlockActivity, BlockInfo, BlockStatus, Cursor, Delivery,
+    Error, Input, Order, Output, Page, Submission, SubmissionStatus, Transaction, Utxo, Webhook,
+    WebhookEvent, WebhookPayload,
+};
 use cardano::block::types::HeaderHash;
+use cardano::hash::Blake2b256;
+use rusqlite::types::ToSql;
+use cbor_event::de::{Deserialize, Deserializer};
+use cbor_event::Len;
 use rusqlite::Connection;
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use cardano::block;
@@ -34,7 +44,7 @@ pub fn prepare_schema(conn: &Connection) -> rusqlite::Result<()> {
                 address integer not null references address(id),
                 unique(tx, address)
             );
-            create index txs_by_address_address on txs_by_address (address);
+            create index if not exists txs_by_address_address on txs_by_address (address);
             create table if not exists input (
                 id integer primary key,
                 tx integer not null references tx(id),
@@ -60,34 +70,234 @@ pub fn prepare_schema(conn: &Connection) -> rusqlite::Result<()> {
             commit;
         "#,
     )?;
+
+    migrate_schema(conn)
+}
+
+/// Schema changes applied on top of the base tables, in order. A database that
+/// went through the migration at index `i` has `user_version` set to `i + 1`.
+const MIGRATIONS: &[&str] = &[
+    // remember the block every transaction comes from, so it can be reverted
+    r#"
+        alter table tx add column block text;
+        create index if not exists tx_block on tx(block);
+    "#,
+    // header fields of every applied block, slot is null for boundary blocks
+    r#"
+        alter table block add column previous text;
+        alter table block add column height integer;
+        alter table block add column epoch integer;
+        alter table block add column slot integer;
+        alter table block add column issuer text;
+        alter table block add column tx_count integer;
+        alter table block add column size integer;
+        create index if not exists block_height on block(height);
+        create index if not exists block_date on block(epoch, slot);
+    "#,
+    // date of the containing block and position of the transaction in it
+    r#"
+        alter table tx add column epoch integer;
+        alter table tx add column slot integer;
+        alter table tx add column index_in_block integer;
+    "#,
+    // the transaction consuming each output, null while it is unspent. The
+    // input indexes make the backfill a lookup per output instead of a scan
+    r#"
+        create index if not exists input_source on input(source_tx, offset);
+        create index if not exists input_tx on input(tx);
+        alter table output add column spent_by integer references tx(id);
+        update output set spent_by = (
+            select input.tx from input
+            where input.source_tx = output.tx and input.offset = output.offset
+        );
+        create index if not exists output_address on output(address, spent_by);
+        create index if not exists output_spent_by on output(spent_by);
+    "#,
+    // address and value of the output consumed by each input
+    r#"
+        alter table input add column address integer references address(id);
+        alter table input add column value integer;
+        update input set
+            address = (
+                select output.address from output
+                where output.tx = input.source_tx and output.offset = input.offset
+            ),
+            value = (
+                select output.value from output
+                where output.tx = input.source_tx and output.offset = input.offset
+            );
+    "#,
+    // cbor of the transaction with its witnesses
+    r#"
+        alter table tx add column raw blob;
+    "#,
+    // last epoch imported from its pack, so the initial import can resume
+    r#"
+        create table if not exists imported_epoch (
+            id integer primary key check (id = 0),
+            epoch integer not null
+        );
+    "#,
+    // webhooks, the transactions they were notified of as confirmed, and the
+    // payloads left to deliver
+    r#"
+        create table if not exists webhook (
+            id integer primary key,
+            url text not null,
+            secret text not null,
+            confirmations integer not null,
+            notified_height integer not null
+        );
+        create table if not exists webhook_address (
+            id integer primary key,
+            webhook integer not null references webhook(id),
+            address text not null,
+            unique(webhook, address)
+        );
+        create index if not exists webhook_address_address on webhook_address(address);
+        create table if not exists webhook_notification (
+            id integer primary key,
+            webhook integer not null references webhook(id),
+            txid text not null,
+            block text not null,
+            unique(webhook, txid)
+        );
+        create index if not exists webhook_notification_block on webhook_notification(block);
+        create table if not exists webhook_delivery (
+            id integer primary key,
+            webhook integer not null references webhook(id),
+            payload text not null,
+            attempts integer not null default 0,
+            next_attempt integer not null,
+            last_error text
+        );
+        create index if not exists webhook_delivery_webhook on webhook_delivery(webhook);
+    "#,
+    // summary counters of every address, kept up to date as blocks are
+    // applied and reverted. The balance is what was received minus what was
+    // sent. The index on the inputs keeps the backfill of `sent` a lookup
+    r#"
+        create index if not exists input_address on input(address);
+        alter table address add column tx_count integer not null default 0;
+        alter table address add column received integer not null default 0;
+        alter table address add column sent integer not null default 0;
+        alter table address add column utxo_count integer not null default 0;
+        alter table address add column first_block text;
+        alter table address add column last_block text;
+        update address set
+            tx_count = (
+                select count(*) from txs_by_address
+                where txs_by_address.address = address.id
+            ),
+            received = (
+                select coalesce(sum(output.value), 0) from output
+                where output.address = address.id
+            ),
+            sent = (
+                select coalesce(sum(input.value), 0) from input
+                where input.address = address.id
+            ),
+            utxo_count = (
+                select count(*) from output
+                where output.address = address.id and output.spent_by is null
+            ),
+            first_block = (
+                select tx.block from txs_by_address join tx
+                on txs_by_address.tx = tx.id
+                where txs_by_address.address = address.id and tx.block is not null
+                order by tx.id limit 1
+            ),
+            last_block = (
+                select tx.block from txs_by_address join tx
+                on txs_by_address.tx = tx.id
+                where txs_by_address.address = address.id and tx.block is not null
+                order by tx.id desc limit 1
+            );
+    "#,
+    // transactions submitted through the api, with the block they were seen
+    // in, null while they are pending
+    r#"
+        create table if not exists pending_tx (
+            txid text primary key,
+            submitted_at integer not null,
+            block text
+        );
+    "#,
+];
+
+fn migrate_schema(conn: &Connection) -> rusqlite::Result<()> {
+    let version: i64 =
+        conn.query_row("pragma user_version", rusqlite::NO_PARAMS, |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating schema to version {}", i + 1);
+        conn.execute_batch(&format!(
+            "begin; {} pragma user_version = {}; commit;",
+            migration,
+            i + 1
+        ))?;
+    }
+
     Ok(())
 }
 
-pub fn insert_tx(conn: &Connection, tx: Tx) -> rusqlite::Result<()> {
+pub fn insert_tx(
+    conn: &Connection,
+    block: &HeaderHash,
+    date: &BlockDate,
+    index_in_block: u32,
+    tx: Tx,
+) -> crate::types::Result<()> {
     let hash = format!("{}", tx.id());
+    let block = format!("{}", block);
     let inputs = tx.inputs;
     let outputs = tx.outputs;
 
+    let slot = match date {
+        BlockDate::Boundary(_) => None,
+        BlockDate::Normal(d) => Some(i64::from(d.slotid)),
+    };
+
     conn.execute(
-        "insert into tx (id, txid)
-        values (NULL, ?1)",
-        params![hash],
-    )?;
+        "insert into tx (id, txid, block, epoch, slot, index_in_block)
+        values (NULL, ?1, ?2, ?3, ?4, ?5)",
+        params![
+            hash,
+            block,
+            date.get_epochid() as i64,
+            slot,
+            index_in_block
+        ],
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(ref failure, _)
+            if failure.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            Error::InvariantViolation(format!("transaction {} is already indexed", hash))
+        }
+        e => e.into(),
+    })?;
 
     let txid = conn.last_insert_rowid();
 
+    // confirms the transaction if it was submitted through the api
+    conn.execute(
+        "UPDATE pending_tx SET block = ?2 WHERE txid = ?1",
+        params![hash, block],
+    )?;
+
     for (idx, output) in outputs.iter().enumerate() {
-        match add_output(&conn, txid, output, idx as u32) {
+        match add_output(&conn, txid, Some(&block), output, idx as u32) {
             Ok(_) => (),
             Err(e) => {
                 error!("Error inserting output: {}", e);
-                return Err(e);
+                return Err(e.into());
             }
         };
     }
 
     for input in inputs {
-        match add_input(&conn, txid, &input) {
+        match add_input(&conn, &hash, txid, &block, &input) {
             Ok(_) => (),
             Err(e) => {
                 error!("Error inserting input: {}", e);
@@ -99,37 +309,126 @@ pub fn insert_tx(conn: &Connection, tx: Tx) -> rusqlite::Result<()> {
     Ok(())
 }
 
-fn add_input(conn: &Connection, txid: i64, input: &TxoPointer) -> rusqlite::Result<()> {
-    let source_tx: i64 = conn.query_row(
-        "SELECT id FROM tx WHERE tx.txid=?1",
-        params![format!("{}", input.id)],
-        |row| row.get(0),
-    )?;
+/// Insert a transaction with its witnesses, keeping `raw`, their encoding as
+/// found in the block. Re-encoding the decoded `TxAux` could change the bytes
+/// the witnesses sign, so `raw` is stored as is once its transaction is
+/// checked to hash to the id.
+pub fn insert_tx_aux(
+    conn: &Connection,
+    block: &HeaderHash,
+    date: &BlockDate,
+    index_in_block: u32,
+    tx_aux: TxAux,
+    raw: &[u8],
+) -> crate::types::Result<()> {
+    let id = tx_aux.tx.id();
+    let txid = format!("{}", id);
+
+    // [tx, witnesses]
+    let invalid = |why: String| Error::DecodeError(format!("transaction {}", txid), why);
+    let mut cursor = std::io::Cursor::new(raw);
+    Deserializer::from(&mut cursor).array().map_err(|e| invalid(e.to_string()))?;
+    let encoding = cbor_span::<Tx>(&mut cursor).map_err(|e| invalid(e.to_string()))?;
+    if Blake2b256::new(encoding) != id {
+        return Err(invalid(
+            "the encoding found in the block doesn't hash to the transaction id".to_string(),
+        ));
+    }
+
+    insert_tx(conn, block, date, index_in_block, tx_aux.tx)?;
+
+    conn.execute("UPDATE tx SET raw = ?1 WHERE txid = ?2", params![raw, txid])?;
+
+    Ok(())
+}
+
+/// Deserialize a `T` at the position of `cursor`, returning the bytes it was
+/// decoded from.
+fn cbor_span<'a, T: Deserialize>(
+    cursor: &mut std::io::Cursor<&'a [u8]>,
+) -> cbor_event::Result<&'a [u8]> {
+    let start = cursor.position() as usize;
+    Deserializer::from(&mut *cursor).deserialize::<T>()?;
+    let raw: &'a [u8] = *cursor.get_ref();
+    Ok(&raw[start..cursor.position() as usize])
+}
+
+/// The encoding of every `TxAux` of the main block `raw`, in block order.
+fn tx_aux_encodings(raw: &[u8]) -> cbor_event::Result<Vec<&[u8]>> {
+    // [1, [header, [[tx aux, ...], ssc, delegation, update], extra]]
+    let mut cursor = std::io::Cursor::new(raw);
+    let length = {
+        let mut deserializer = Deserializer::from(&mut cursor);
+        deserializer.array()?;
+        deserializer.unsigned_integer()?;
+        deserializer.array()?;
+        deserializer.deserialize::<block::normal::BlockHeader>()?;
+        deserializer.array()?;
+        deserializer.array()?
+    };
+
+    let mut encodings = vec![];
+    loop {
+        let done = match length {
+            Len::Len(length) => encodings.len() as u64 == length,
+            Len::Indefinite => raw.get(cursor.position() as usize) == Some(&0xff),
+        };
+        if done {
+            return Ok(encodings);
+        }
+
+        encodings.push(cbor_span::<TxAux>(&mut cursor)?);
+    }
+}
+
+fn add_input(
+    conn: &Connection,
+    hash: &str,
+    txid: i64,
+    block: &str,
+    input: &TxoPointer,
+) -> crate::types::Result<()> {
+    let (source_tx, address, value): (i64, i64, i64) = match conn.query_row(
+        "SELECT tx.id, output.address, output.value
+        FROM tx JOIN output
+        ON tx.id = output.tx
+        WHERE tx.txid = ?1 AND output.offset = ?2",
+        params![format!("{}", input.id), input.index],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ) {
+        Ok(source) => source,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            return Err(Error::InvariantViolation(format!(
+                "transaction {} spends output {}@{} which is not indexed",
+                hash, input.id, input.index
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     conn.execute(
-        "insert into input (id, tx, source_tx, offset)
-        values (NULL, ?1, ?2, ?3)",
-        params![txid, source_tx, input.index],
+        "insert into input (id, tx, source_tx, offset, address, value)
+        values (NULL, ?1, ?2, ?3, ?4, ?5)",
+        params![txid, source_tx, input.index, address, value],
     )?;
 
     conn.execute(
-        "insert or ignore into txs_by_address
-        select null, ?3, output.address from 
-            tx join output
-            on tx.id = output.tx
-        where
-            tx.txid = ?1 and
-            output.offset = ?2
-        ",
-        params![format!("{}", input.id), input.index, txid],
+        "UPDATE output SET spent_by = ?1
+        WHERE tx = ?2 AND offset = ?3",
+        params![txid, source_tx, input.index],
     )?;
 
+    record_activity(conn, txid, Some(block), address, 0, value, -1)?;
+
     Ok(())
 }
 
+/// Insert an output of the transaction `txid`, from `block` or from the
+/// genesis.
 fn add_output(
     conn: &rusqlite::Connection,
     txid: i64,
+    block: Option<&str>,
     output: &TxOut,
     idx: u32,
 ) -> rusqlite::Result<()> {
@@ -155,22 +454,67 @@ fn add_output(
         params!(txid, address_id, u64::from(output.value) as i64, idx as u32),
     )?;
 
-    conn.execute(
+    record_activity(
+        conn,
+        txid,
+        block,
+        address_id,
+        u64::from(output.value) as i64,
+        0,
+        1,
+    )?;
+
+    Ok(())
+}
+
+/// Add the transaction `txid` of `block`, `None` for the genesis, to the
+/// history of `address` and update the summary counters of the address by the
+/// values and unspent outputs it moves.
+fn record_activity(
+    conn: &Connection,
+    txid: i64,
+    block: Option<&str>,
+    address: i64,
+    received: i64,
+    sent: i64,
+    utxos: i64,
+) -> rusqlite::Result<()> {
+    let new_tx = conn.execute(
         "insert or ignore into txs_by_address (id, tx, address)
         values (NULL, ?1, ?2)
         ",
-        params!(txid, address_id),
+        params![txid, address],
+    )?;
+
+    conn.execute(
+        "UPDATE address SET
+            tx_count = tx_count + ?2,
+            received = received + ?3,
+            sent = sent + ?4,
+            utxo_count = utxo_count + ?5,
+            first_block = coalesce(first_block, ?6),
+            last_block = coalesce(?6, last_block)
+        WHERE id = ?1",
+        params![address, new_tx as i64, received, sent, utxos, block],
     )?;
 
     Ok(())
 }
 
-pub fn apply_initial_state(conn: &mut Connection, utxos: &Utxos) -> rusqlite::Result<()> {
+pub fn apply_initial_state(conn: &mut Connection, utxos: &Utxos) -> crate::types::Result<()> {
     if let Some(_) = last_applied_block(&conn)? {
         return Ok(());
     }
 
-    let transaction = conn.transaction().unwrap();
+    // applied by an import interrupted before its first epoch
+    let txs: i64 = conn.query_row("SELECT count(*) FROM tx", rusqlite::NO_PARAMS, |row| {
+        row.get(0)
+    })?;
+    if txs > 0 {
+        return Ok(());
+    }
+
+    let transaction = conn.transaction()?;
 
     for (k, v) in utxos {
         transaction.execute(
@@ -181,43 +525,70 @@ pub fn apply_initial_state(conn: &mut Connection, utxos: &Utxos) -> rusqlite::Re
 
         let txid = transaction.last_insert_rowid();
 
-        add_output(&transaction, txid, &v, k.index)?;
+        add_output(&transaction, txid, None, &v, k.index)?;
     }
 
     transaction.commit()?;
     Ok(())
 }
 
-pub fn inputs(conn: &Connection, tx: i64) -> rusqlite::Result<Vec<Input>> {
+/// The inputs of `tx` with the outputs they spend. An input whose output was
+/// never resolved, which only databases migrated from older versions can
+/// hold, is an error rather than being left out of the totals.
+pub fn inputs(conn: &Connection, tx: i64) -> crate::types::Result<Vec<Input>> {
     let mut inputs_stmt = conn
         .prepare(
-            "SELECT input.offset, source_tx.txid
+            "SELECT tx.txid, input.offset, source_tx.txid, address.address, input.value
     FROM 
     tx 
     JOIN
     input
         ON input.tx = tx.id
-    JOIN
+    LEFT JOIN
     tx as source_tx
         ON input.source_tx = source_tx.id
+    LEFT JOIN
+    address
+        ON input.address = address.id
     WHERE
-        tx.id = ?1",
-        )
-        .unwrap();
+        tx.id = ?1
+    ORDER BY
+        input.id",
+        )?;
 
-    let inputs_iter = inputs_stmt
+    let rows = inputs_stmt
         .query_map(params![tx], |row| {
-            Ok(Input {
-                index: row.get(0)?,
-                id: row.get(1)?,
-            })
-        })
-        .unwrap();
-
-    let inputs: rusqlite::Result<Vec<Input>> =
-        inputs_iter.map(|input| Ok(input?)).collect();
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i32>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })?;
+
+    let mut inputs = vec![];
+
+    for row in rows {
+        match row? {
+            (_, index, Some(id), Some(address), Some(value)) => inputs.push(Input {
+                index,
+                id,
+                address,
+                value,
+            }),
+            (txid, index, id, _, _) => {
+                return Err(Error::InvariantViolation(format!(
+                    "input {}@{} of transaction {} spends an output which is not indexed",
+                    id.unwrap_or_else(|| "?".to_string()),
+                    index,
+                    txid
+                )))
+            }
+        }
+    }
 
-    Ok(inputs?)
+    Ok(inputs)
 }
 
 pub fn outputs(conn: &Connection, tx: i64) -> rusqlite::Result<Vec<Output>> {
@@ -236,8 +607,7 @@ pub fn outputs(conn: &Connection, tx: i64) -> rusqlite::Result<Vec<Output>> {
     ORDER BY
         output.offset
     ",
-        )
-        .unwrap();
+        )?;
 
     let outputs_iter = outputs_stmt
         .query_map(params![tx], |row| {
@@ -245,8 +615,7 @@ pub fn outputs(conn: &Connection, tx: i64) -> rusqlite::Result<Vec<Output>> {
                 address: row.get(0)?,
                 value: row.get(1)?,
             })
-        })
-        .unwrap();
+        })?;
 
     let outputs: rusqlite::Result<Vec<Output>> =
         outputs_iter.map(|output| Ok(output?)).collect();
@@ -254,235 +623,2551 @@ pub fn outputs(conn: &Connection, tx: i64) -> rusqlite::Result<Vec<Output>> {
     Ok(outputs?)
 }
 
-pub fn transaction(conn: &Connection, txid: String) -> rusqlite::Result<Transaction> {
-    let tx: i64 = conn
-        .query_row(
-            "SELECT id FROM tx WHERE 
-                txid=?1
-            ",
-            params![txid],
-            |row| row.get(0),
-        )?;
+/// Read the columns of `tx` selected by `transaction` and `transactions_of`,
+/// leaving inputs and outputs to be filled in.
+fn transaction_row(row: &rusqlite::Row) -> rusqlite::Result<(i64, Transaction)> {
+    Ok((
+        row.get(0)?,
+        Transaction {
+            txid: row.get(1)?,
+            block: row.get(2)?,
+            epoch: row.get(3)?,
+            slot: row.get(4)?,
+            index_in_block: row.get(5)?,
+            inputs: vec![],
+            outputs: vec![],
+            total_input: 0,
+            total_output: 0,
+            fee: None,
+        },
+    ))
+}
 
-    Ok(Transaction {
-        txid,
-        inputs: inputs(conn, tx)?,
-        outputs: outputs(conn, tx)?,
+/// Fill in the inputs and outputs of a transaction read by `transaction_row`,
+/// and the amounts computed from them.
+fn complete_transaction(
+    conn: &Connection,
+    tx: i64,
+    mut transaction: Transaction,
+) -> crate::types::Result<Transaction> {
+    transaction.inputs = inputs(conn, tx)?;
+    transaction.outputs = outputs(conn, tx)?;
+
+    transaction.total_input = transaction.inputs.iter().map(|input| input.value).sum();
+    transaction.total_output = transaction.outputs.iter().map(|output| output.value).sum();
+
+    // transactions without inputs come from the genesis and pay no fee
+    if !transaction.inputs.is_empty() {
+        transaction.fee = Some(transaction.total_input - transaction.total_output);
+    }
+
+    Ok(transaction)
+}
+
+pub fn transaction(conn: &Connection, txid: String) -> crate::types::Result<Transaction> {
+    let (tx, transaction) = match conn.query_row(
+        "SELECT id, txid, block, epoch, slot, index_in_block FROM tx WHERE 
+            txid=?1
+        ",
+        params![txid],
+        transaction_row,
+    ) {
+        Ok(row) => row,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            return Err(Error::NotFound(format!("transaction {}", txid)))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(complete_transaction(conn, tx, transaction)?)
+}
+
+/// The cbor of a transaction with its witnesses. It is unknown for genesis
+/// transactions and for transactions imported by older versions.
+pub fn raw_transaction(conn: &Connection, txid: &str) -> rusqlite::Result<Option<Vec<u8>>> {
+    conn.query_row("SELECT raw FROM tx WHERE txid = ?1", params![txid], |row| {
+        row.get(0)
     })
 }
 
+/// A page of the merged history of `addresses`, in chain order, with every
+/// transaction listed once. The returned cursor points to the next page, if
+/// there is one.
+///
+/// The order relies on the heights of the blocks, which databases of older
+/// versions only have once `backfill-block-metadata` ran.
 pub fn transactions_of(
     conn: &Connection,
-    address: ExtendedAddr,
-) -> rusqlite::Result<Vec<Transaction>> {
-    let mut transactions_stmt = conn
+    addresses: &[ExtendedAddr],
+    page: &Page,
+) -> crate::types::Result<(Vec<Transaction>, Option<Cursor>)> {
+    let (comparison, direction) = match page.order {
+        Order::Asc => (">", "ASC"),
+        Order::Desc => ("<", "DESC"),
+    };
+
+    let placeholders: Vec<String> = (0..addresses.len())
+        .map(|i| format!("?{}", i + 4))
+        .collect();
+
+    // the position of genesis transactions, and of transactions imported
+    // without their index, is their row
+    let mut transactions_stmt = conn.prepare(&format!(
+        "SELECT id, txid, block, epoch, slot, index_in_block, height, position FROM (
+            SELECT DISTINCT tx.id, tx.txid, tx.block, tx.epoch, tx.slot, tx.index_in_block,
+                coalesce(block.height, -1) AS height,
+                coalesce(tx.index_in_block, tx.id) AS position
+            FROM tx JOIN txs_by_address
+            ON tx.id = txs_by_address.tx
+            JOIN address
+            ON txs_by_address.address = address.id
+            LEFT JOIN block
+            ON tx.block = block.id
+            WHERE address.address IN ({})
+        )
+        WHERE ?1 IS NULL OR height {} ?1 OR (height = ?1 AND position {} ?2)
+        ORDER BY height {}, position {}
+        LIMIT ?3
+    ",
+        placeholders.join(", "),
+        comparison,
+        comparison,
+        direction,
+        direction
+    ))?;
+
+    let height = page.cursor.map(|cursor| cursor.height);
+    let index = page.cursor.map(|cursor| cursor.index);
+    // one extra row tells if there is a next page
+    let limit = i64::from(page.limit) + 1;
+    let addresses: Vec<String> = addresses
+        .iter()
+        .map(|address| format!("{}", address))
+        .collect();
+
+    let mut values: Vec<&dyn ToSql> = vec![&height, &index, &limit];
+    values.extend(addresses.iter().map(|address| address as &dyn ToSql));
+
+    let transaction_iter = transactions_stmt.query_map(&values, |row| {
+        let (tx, transaction) = transaction_row(row)?;
+        let cursor = Cursor {
+            height: row.get(6)?,
+            index: row.get(7)?,
+        };
+        Ok((tx, transaction, cursor))
+    })?;
+
+    let mut rows = transaction_iter.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let next = if rows.len() > page.limit as usize {
+        rows.truncate(page.limit as usize);
+        rows.last().map(|(_, _, cursor)| *cursor)
+    } else {
+        None
+    };
+
+    let transactions = rows
+        .into_iter()
+        .map(|(tx, transaction, _)| complete_transaction(conn, tx, transaction))
+        .collect::<crate::types::Result<Vec<_>>>()?;
+
+    Ok((transactions, next))
+}
+
+/// The addresses whose history includes the transaction `txid`.
+pub fn addresses_of(conn: &Connection, txid: &str) -> rusqlite::Result<Vec<String>> {
+    let mut addresses_stmt = conn
         .prepare(
-            "SELECT tx.id, tx.txid 
+            "SELECT address.address
         FROM tx JOIN txs_by_address
         ON tx.id = txs_by_address.tx
         JOIN address
         ON txs_by_address.address = address.id
-        WHERE address.address = ?1
+        WHERE tx.txid = ?1
     ",
-        )
-        .unwrap();
-    let transaction_iter = transactions_stmt
-        .query_map(params![format!("{}", address)], |row| {
-            let id: i64 = row.get(0)?;
-            let txid: String = row.get(1)?;
-            Ok((id, txid))
-        })
-        .unwrap();
+        )?;
 
-    transaction_iter
-        .map(|transaction| {
-            let (tx, txid) = transaction?;
+    let addresses_iter = addresses_stmt
+        .query_map(params![txid], |row| row.get(0))?;
 
-            Ok(Transaction {
-                txid,
-                inputs: inputs(conn, tx)?,
-                outputs: outputs(conn, tx)?,
-            })
-        })
-        .collect()
+    addresses_iter.collect()
 }
 
-pub fn apply_block(conn: &Connection, block: &Block) -> rusqlite::Result<()> {
-    if let Some(payload) = block.get_transactions() {
-        for tx_aux in payload {
-                insert_tx(conn, tx_aux.tx)?;
-        }
-    }
-
-    let hash = block.header().compute_hash();
+pub fn utxos_of(conn: &Connection, address: ExtendedAddr) -> rusqlite::Result<Vec<Utxo>> {
+    let mut utxos_stmt = conn
+        .prepare(
+            "SELECT tx.txid, output.offset, output.value
+        FROM output JOIN tx
+        ON output.tx = tx.id
+        JOIN address
+        ON output.address = address.id
+        WHERE address.address = ?1 AND output.spent_by IS NULL
+        ORDER BY output.id
+    ",
+        )?;
 
-    match conn.execute(
-        "insert or replace into last_block(id, block)
-        values (0, ?1)",
-        params![format!("{}", hash)],
-    ) {
-        Ok(_) => (),
-        Err(e) => error!("Couldn't update last_block to {}: {}", hash, e),
-    }
+    let utxos_iter = utxos_stmt
+        .query_map(params![format!("{}", address)], |row| {
+            Ok(Utxo {
+                txid: row.get(0)?,
+                index: row.get(1)?,
+                value: row.get(2)?,
+            })
+        })?;
 
-    Ok(())
+    utxos_iter.collect()
 }
 
-pub fn last_applied_block(conn: &Connection) -> rusqlite::Result<Option<HeaderHash>> {
-    let last_block: Option<String> = match conn.query_row(
-        "SELECT block FROM last_block WHERE id = 0",
+/// Height of the last applied block, if it is known.
+pub fn last_applied_height(conn: &Connection) -> rusqlite::Result<Option<i64>> {
+    match conn.query_row(
+        "SELECT block.height FROM last_block JOIN block
+        ON last_block.block = block.id
+        WHERE last_block.id = 0",
         rusqlite::NO_PARAMS,
         |row| row.get(0),
     ) {
-        Ok(string) => Some(string),
-        Err(rusqlite::Error::QueryReturnedNoRows) => None,
-        Err(e) => return Err(e),
-    };
-    Ok(last_block.map(|hash| HeaderHash::from_str(&hash).unwrap()))
+        Ok(height) => Ok(height),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
-pub fn next_block(conn: &Connection, block: HeaderHash) -> rusqlite::Result<Option<HeaderHash>> {
-    let result: Option<String> = conn.query_row(
-        "SELECT next FROM block WHERE id = ?1",
+/// The epoch and slot of an indexed block, the slot being `None` for boundary
+/// blocks. `None` if the block's metadata is not recorded.
+pub fn block_date(
+    conn: &Connection,
+    block: &HeaderHash,
+) -> rusqlite::Result<Option<(i64, Option<i64>)>> {
+    match conn.query_row(
+        "SELECT epoch, slot FROM block WHERE id = ?1",
         params![format!("{}", block)],
-        |row| row.get(0),
-    )?;
+        |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+    ) {
+        Ok((Some(epoch), slot)) => Ok(Some((epoch, slot))),
+        Ok((None, _)) | Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The applied blocks matching `condition`, by height, with their position in
+/// that order. Only the blocks with their metadata recorded are applied, the
+/// ones only in the index have none, so `next` is left out until the next
+/// block is applied.
+///
+/// Blocks without a height, which databases of older versions hold until
+/// `backfill-block-metadata` ran, come first, by row.
+fn blocks_where(
+    conn: &Connection,
+    condition: &str,
+    values: &[&dyn ToSql],
+    order: Order,
+    limit: Option<u32>,
+) -> crate::types::Result<Vec<(BlockInfo, Cursor)>> {
+    let direction = match order {
+        Order::Asc => "ASC",
+        Order::Desc => "DESC",
+    };
+
+    // shared with the query of the transactions, so the page is read once
+    let selection = format!(
+        "FROM block LEFT JOIN block AS next
+        ON block.next = next.id AND next.epoch IS NOT NULL
+        WHERE block.epoch IS NOT NULL AND {}
+        ORDER BY coalesce(block.height, -1) {}, block.rowid {}
+        LIMIT {}",
+        condition,
+        direction,
+        direction,
+        limit.map_or(-1, i64::from)
+    );
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT block.id, block.previous, next.id, block.height, block.epoch, block.slot,
+            block.issuer, block.tx_count, block.size, coalesce(block.height, -1), block.rowid
+        {}",
+        selection
+    ))?;
+
+    let mut blocks = stmt
+        .query_map(values, |row| {
+            let block = BlockInfo {
+                hash: row.get(0)?,
+                previous: row.get(1)?,
+                next: row.get(2)?,
+                height: row.get(3)?,
+                epoch: row.get(4)?,
+                slot: row.get(5)?,
+                issuer: row.get(6)?,
+                tx_count: row.get(7)?,
+                size: row.get(8)?,
+                transactions: vec![],
+            };
+            let cursor = Cursor {
+                height: row.get(9)?,
+                index: row.get(10)?,
+            };
+            Ok((block, cursor))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut txs_stmt = conn.prepare(&format!(
+        "SELECT block, txid FROM tx
+        WHERE block IN (SELECT block.id {})
+        ORDER BY block, index_in_block",
+        selection
+    ))?;
+
+    let mut transactions: HashMap<String, Vec<String>> = HashMap::new();
+    for row in txs_stmt.query_map(values, |row| Ok((row.get(0)?, row.get(1)?)))? {
+        let (block, txid): (String, String) = row?;
+        transactions.entry(block).or_insert_with(Vec::new).push(txid);
+    }
 
-    Ok(result.map(|h| HeaderHash::from_str(&h).unwrap()))
+    for (block, _) in blocks.iter_mut() {
+        if let Some(txids) = transactions.remove(&block.hash) {
+            block.transactions = txids;
+        }
+    }
+
+    Ok(blocks)
 }
 
-pub fn update_block_index<F>(
-    conn: &mut Connection,
-    to: HeaderHash,
-    get_previous: F,
-) -> crate::types::Result<()>
-where
-    F: Fn(HeaderHash) -> std::result::Result<HeaderHash, crate::types::Error>,
-{
-    info!("Updating block index");
-    let transaction = conn.transaction().unwrap();
+fn single_block(
+    blocks: Vec<(BlockInfo, Cursor)>,
+    what: String,
+) -> crate::types::Result<BlockInfo> {
+    blocks
+        .into_iter()
+        .next()
+        .map(|(block, _)| block)
+        .ok_or_else(|| Error::NotFound(what))
+}
 
-    let last_block: String = transaction.query_row(
-        "SELECT id FROM block WHERE next IS NULL",
-        rusqlite::NO_PARAMS,
-        |row| row.get(0),
+pub fn block_by_hash(conn: &Connection, hash: &HeaderHash) -> crate::types::Result<BlockInfo> {
+    let hash = format!("{}", hash);
+    let blocks = blocks_where(conn, "block.id = ?1", &[&hash], Order::Asc, None)?;
+    single_block(blocks, format!("block {}", hash))
+}
+
+/// The applied block at `height`, counting boundary blocks.
+pub fn block_by_height(conn: &Connection, height: i64) -> crate::types::Result<BlockInfo> {
+    let blocks = blocks_where(conn, "block.height = ?1", &[&height], Order::Asc, None)?;
+    single_block(blocks, format!("block at height {}", height))
+}
+
+/// The applied block of a slot, the boundary block of the epoch if `slot` is
+/// `None`.
+pub fn block_by_date(
+    conn: &Connection,
+    epoch: i64,
+    slot: Option<i64>,
+) -> crate::types::Result<BlockInfo> {
+    let blocks = blocks_where(
+        conn,
+        "block.epoch = ?1 AND block.slot IS ?2",
+        &[&epoch, &slot],
+        Order::Asc,
+        None,
     )?;
 
-    let mut next = None;
-    let mut cursor = to;
+    let what = match slot {
+        Some(slot) => format!("block at slot {} of epoch {}", slot, epoch),
+        None => format!("boundary block of epoch {}", epoch),
+    };
+    single_block(blocks, what)
+}
 
-    loop {
-        transaction.execute(
-            "insert or replace into block(id, next)
-            values (?1, ?2)
-            ",
-            params![format!("{}", &cursor), next.map(|h| format!("{}", h))],
-        )?;
+/// The `count` last applied blocks, newest first.
+pub fn latest_blocks(conn: &Connection, count: u32) -> crate::types::Result<Vec<BlockInfo>> {
+    let blocks = blocks_where(conn, "1", &[], Order::Desc, Some(count))?;
+    Ok(blocks.into_iter().map(|(block, _)| block).collect())
+}
 
-        info!("Inserting block {}", format!("{}", &cursor));
+/// A page of the applied blocks of `epoch`, by height. The returned cursor
+/// points to the next page, if there is one.
+pub fn epoch_blocks(
+    conn: &Connection,
+    epoch: i64,
+    page: &Page,
+) -> crate::types::Result<(Vec<BlockInfo>, Option<Cursor>)> {
+    let comparison = match page.order {
+        Order::Asc => ">",
+        Order::Desc => "<",
+    };
 
-        if format!("{}", cursor) == last_block {
-            break;
-        }
+    let condition = format!(
+        "block.epoch = ?1 AND (?2 IS NULL
+            OR coalesce(block.height, -1) {} ?2
+            OR (coalesce(block.height, -1) = ?2 AND block.rowid {} ?3))",
+        comparison, comparison
+    );
+
+    let height = page.cursor.map(|cursor| cursor.height);
+    let index = page.cursor.map(|cursor| cursor.index);
+    // one extra row tells if there is a next page
+    let mut rows = blocks_where(
+        conn,
+        &condition,
+        &[&epoch, &height, &index],
+        page.order,
+        Some(page.limit + 1),
+    )?;
 
-        next = Some(cursor.clone());
-        cursor = get_previous(cursor)?;
-    }
+    let next = if rows.len() > page.limit as usize {
+        rows.truncate(page.limit as usize);
+        rows.last().map(|(_, cursor)| *cursor)
+    } else {
+        None
+    };
 
-    transaction.commit()?;
-    Ok(())
+    Ok((rows.into_iter().map(|(block, _)| block).collect(), next))
 }
 
-use std::time::{Instant};
-pub fn sync_from_epochs<F>(
-    conn: &mut Connection,
-    first_unstable_epoch: EpochId,
-    get_epoch: F,
-) -> rusqlite::Result<()>
-where
-    F: Fn(EpochId) -> Vec<u8>,
-{
-    for i in 0..first_unstable_epoch {
+/// Sum the unspent outputs of `address`. Outputs from the genesis count as
+/// stable, and outputs of blocks as unstable unless they are deeper than
+/// `stability_depth` blocks, which can't be told when the height of their
+/// block or of the last applied one is unknown.
+pub fn balance_of(
+    conn: &Connection,
+    address: ExtendedAddr,
+    stability_depth: usize,
+) -> rusqlite::Result<Balance> {
+    let first_unstable = last_applied_height(conn)?.map(|height| height - stability_depth as i64);
+
+    conn.query_row(
+        "SELECT
+            coalesce(sum(output.value), 0),
+            coalesce(sum(CASE
+                WHEN tx.block IS NULL THEN 0
+                WHEN block.height IS NULL OR ?2 IS NULL OR block.height >= ?2 THEN output.value
+                ELSE 0
+            END), 0)
+        FROM output JOIN address
+        ON output.address = address.id
+        JOIN tx
+        ON output.tx = tx.id
+        LEFT JOIN block
+        ON tx.block = block.id
+        WHERE address.address = ?1 AND output.spent_by IS NULL
+    ",
+        params![format!("{}", address), first_unstable],
+        |row| {
+            let total: i64 = row.get(0)?;
+            let unstable: i64 = row.get(1)?;
+            Ok(Balance {
+                total,
+                stable: total - unstable,
+                unstable,
+            })
+        },
+    )
+}
 
-        let transaction = conn.transaction().unwrap();
+/// The transactions of the applied block `block` and the addresses they
+/// touch.
+pub fn block_activity(conn: &Connection, block: &HeaderHash) -> rusqlite::Result<BlockActivity> {
+    let hash = format!("{}", block);
+    let date = block_date(conn, block)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT tx.txid, address.address
+        FROM tx JOIN txs_by_address
+        ON tx.id = txs_by_address.tx
+        JOIN address
+        ON txs_by_address.address = address.id
+        WHERE tx.block = ?1
+        ORDER BY tx.index_in_block, address.address
+    ",
+    )?;
 
-        info!("Epoch: {}", i);
+    let rows = stmt.query_map(params![hash], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
 
-        let epoch = get_epoch(i);
-        let mut reader = Reader::init(epoch.as_slice()).unwrap();
+    let mut transactions: Vec<Activity> = vec![];
+    for row in rows {
+        let (txid, address) = row?;
 
-        let mut hashes = vec![];
+        match transactions.last_mut() {
+            Some(ref mut last) if last.txid == txid => last.addresses.push(address),
+            _ => transactions.push(Activity {
+                txid,
+                addresses: vec![address],
+            }),
+        }
+    }
 
-        let now = Instant::now();
+    Ok(BlockActivity {
+        block: hash,
+        epoch: date.map(|(epoch, _)| epoch),
+        slot: date.and_then(|(_, slot)| slot),
+        transactions,
+    })
+}
 
-        while let Some(b) = reader.next_block().unwrap() {
-            let block = block::RawBlock(b).decode().unwrap();
+/// The summary counters of `address`, all zero if it was never seen.
+pub fn address_summary(
+    conn: &Connection,
+    address: &ExtendedAddr,
+) -> rusqlite::Result<AddressSummary> {
+    let address = format!("{}", address);
 
-            apply_block(&transaction, &block)?;
+    let block_status = |hash: Option<String>, epoch: Option<i64>, slot: Option<i64>| {
+        hash.map(|hash| BlockStatus { hash, epoch, slot })
+    };
 
-            let hash = block.header().compute_hash();
-            hashes.push(format!("{}", hash));
-        }
+    match conn.query_row(
+        "SELECT address.tx_count, address.received, address.sent, address.utxo_count,
+            address.first_block, first.epoch, first.slot,
+            address.last_block, last.epoch, last.slot
+        FROM address LEFT JOIN block AS first
+        ON address.first_block = first.id
+        LEFT JOIN block AS last
+        ON address.last_block = last.id
+        WHERE address.address = ?1",
+        params![address],
+        |row| {
+            let received: i64 = row.get(1)?;
+            let sent: i64 = row.get(2)?;
 
-        match transaction.query_row(
-            "SELECT id FROM block WHERE next IS NULL",
-            rusqlite::NO_PARAMS,
-            |row| row.get(0),
-        ) {
-            Ok(hash) => {
-                let hash: String = hash;
-                transaction.execute(
-                    "insert or replace into block(id, next)
-                values (?1, ?2)
-                ",
-                    params![format!("{}", hash), hashes[0]],
-                )?;
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => (),
-            Err(e) => return Err(e),
-        };
+            Ok(AddressSummary {
+                address: address.clone(),
+                first_seen: block_status(row.get(4)?, row.get(5)?, row.get(6)?),
+                last_seen: block_status(row.get(7)?, row.get(8)?, row.get(9)?),
+                tx_count: row.get(0)?,
+                total_received: received,
+                total_sent: sent,
+                balance: received - sent,
+                utxo_count: row.get(3)?,
+            })
+        },
+    ) {
+        Ok(summary) => Ok(summary),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(AddressSummary {
+            address,
+            first_seen: None,
+            last_seen: None,
+            tx_count: 0,
+            total_received: 0,
+            total_sent: 0,
+            balance: 0,
+            utxo_count: 0,
+        }),
+        Err(e) => Err(e),
+    }
+}
 
-        for i in 0..hashes.len() {
-            let block = &hashes[i];
-            let next = hashes.get(i + 1);
+/// Apply `block`, decoded from `raw`, on the chain starting from the genesis
+/// `genesis_prev`.
+pub fn apply_block(
+    conn: &Connection,
+    genesis_prev: &HeaderHash,
+    block: &Block,
+    raw: &[u8],
+) -> crate::types::Result<()> {
+    let header = block.header();
+    let hash = header.compute_hash();
+    let date = header.get_blockdate();
 
-            transaction.execute(
-                "insert or replace into block(id, next)
-                values (?1, ?2)
-                ",
-                params![format!("{}", block), next.map(|h| format!("{}", h))],
-            )?;
+    if let Some(payload) = block.get_transactions() {
+        let encodings = tx_aux_encodings(raw)
+            .map_err(|e| Error::DecodeError(format!("block {}", hash), e.to_string()))?;
+        let txs: Vec<TxAux> = payload.into_iter().collect();
+
+        if txs.len() != encodings.len() {
+            return Err(Error::DecodeError(
+                format!("block {}", hash),
+                format!("{} transactions, {} encodings", txs.len(), encodings.len()),
+            ));
         }
 
-        transaction.commit()?;
-        info!("Finished epoch {} in {}", i, now.elapsed().as_millis());
+        for (index, (tx_aux, raw)) in txs.into_iter().zip(encodings).enumerate() {
+            insert_tx_aux(conn, &hash, &date, index as u32, tx_aux, raw)?;
+        }
     }
 
-    Ok(())
+    update_block_metadata(conn, genesis_prev, block, raw.len())?;
+
+    match conn.execute(
+        "insert or replace into last_block(id, block)
+        values (0, ?1)",
+        params![format!("{}", hash)],
+    ) {
+        Ok(_) => (),
+        Err(e) => error!("Couldn't update last_block to {}: {}", hash, e),
+    }
+
+    Ok(())
+}
+
+/// Record the header fields of `block` in its `block` row, adding the row if
+/// the block is not indexed yet.
+///
+/// The height is the position of the block in the chain, counting boundary
+/// blocks, so it is left unset if the parent has none or isn't indexed, unless
+/// the parent is the genesis `genesis_prev`. `size` is the length of
+/// the block as received, which a re-encoding of `block` may not match.
+pub fn update_block_metadata(
+    conn: &Connection,
+    genesis_prev: &HeaderHash,
+    block: &Block,
+    size: usize,
+) -> rusqlite::Result<()> {
+    let header = block.header();
+    let hash = format!("{}", header.compute_hash());
+    let previous = format!("{}", header.previous_header());
+
+    let height: Option<i64> = match conn.query_row(
+        "SELECT height FROM block WHERE id = ?1",
+        params![previous],
+        |row| row.get(0),
+    ) {
+        Ok(height) => {
+            let height: Option<i64> = height;
+            height.map(|h| h + 1)
+        }
+        // only the first block of the chain follows the genesis
+        Err(rusqlite::Error::QueryReturnedNoRows) if header.previous_header() == *genesis_prev => {
+            Some(0)
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e),
+    };
+
+    let (epoch, slot) = match header.get_blockdate() {
+        BlockDate::Boundary(epoch) => (epoch as i64, None),
+        BlockDate::Normal(date) => (date.epoch as i64, Some(i64::from(date.slotid))),
+    };
+
+    let issuer = match block {
+        Block::MainBlock(ref blk) => Some(format!("{}", blk.header.consensus.leader_key)),
+        Block::BoundaryBlock(_) => None,
+    };
+
+    let tx_count = match block.get_transactions() {
+        Some(payload) => payload.into_iter().count() as i64,
+        None => 0,
+    };
+
+    conn.execute(
+        "insert or ignore into block(id, next)
+        values (?1, NULL)",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "UPDATE block SET
+            previous = ?2,
+            height = ?3,
+            epoch = ?4,
+            slot = ?5,
+            issuer = ?6,
+            tx_count = ?7,
+            size = ?8
+        WHERE id = ?1",
+        params![hash, previous, height, epoch, slot, issuer, tx_count, size as i64],
+    )?;
+
+    Ok(())
+}
+
+/// Fill in the metadata of the applied blocks that were imported before it was
+/// recorded, walking the index from `genesis`. Progress is committed every
+/// `BACKFILL_BATCH` blocks, so an interrupted backfill picks up where it
+/// stopped.
+pub fn backfill_block_metadata<F>(
+    conn: &mut Connection,
+    genesis_prev: &HeaderHash,
+    genesis: HeaderHash,
+    get_raw_block: F,
+) -> crate::types::Result<u64>
+where
+    F: Fn(&HeaderHash) -> std::result::Result<block::RawBlock, crate::types::Error>,
+{
+    const BACKFILL_BATCH: u64 = 1000;
+
+    let last = match last_applied_block(conn)? {
+        Some(last) => last,
+        None => return Ok(0),
+    };
+
+    let mut count = 0;
+    let mut cursor = Some(genesis);
+    let mut transaction = conn.transaction()?;
+
+    while let Some(hash) = cursor {
+        let missing: bool = transaction.query_row(
+            "SELECT height IS NULL FROM block WHERE id = ?1",
+            params![format!("{}", hash)],
+            |row| row.get(0),
+        )?;
+
+        if missing {
+            let raw = get_raw_block(&hash)?;
+            let block = raw
+                .decode()
+                .map_err(|e| Error::DecodeError(format!("block {}", hash), format!("{}", e)))?;
+            update_block_metadata(&transaction, genesis_prev, &block, raw.0.len())?;
+            count = count + 1;
+
+            if count % BACKFILL_BATCH == 0 {
+                transaction.commit()?;
+                info!("{} blocks backfilled, last one {}", count, hash);
+                transaction = conn.transaction()?;
+            }
+        }
+
+        if hash == last {
+            break;
+        }
+
+        cursor = next_block(&transaction, hash)?;
+    }
+
+    transaction.commit()?;
+    Ok(count)
+}
+
+/// Undo everything `apply_block` inserted for the given block, including its
+/// share of the address summaries. Blocks must be reverted newest first.
+pub fn revert_block(conn: &Connection, block: &HeaderHash) -> rusqlite::Result<()> {
+    let hash = format!("{}", block);
+
+    // the summary counters first, while the rows of the block are there
+    conn.execute(
+        "UPDATE address SET
+            sent = sent - (
+                SELECT coalesce(sum(input.value), 0) FROM input JOIN tx
+                ON input.tx = tx.id
+                WHERE tx.block = ?1 AND input.address = address.id
+            ),
+            utxo_count = utxo_count + (
+                SELECT count(*) FROM input JOIN tx
+                ON input.tx = tx.id
+                WHERE tx.block = ?1 AND input.address = address.id
+            )
+        WHERE id IN (
+            SELECT input.address FROM input JOIN tx
+            ON input.tx = tx.id
+            WHERE tx.block = ?1
+        )",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "UPDATE address SET
+            received = received - (
+                SELECT coalesce(sum(output.value), 0) FROM output JOIN tx
+                ON output.tx = tx.id
+                WHERE tx.block = ?1 AND output.address = address.id
+            ),
+            utxo_count = utxo_count - (
+                SELECT count(*) FROM output JOIN tx
+                ON output.tx = tx.id
+                WHERE tx.block = ?1 AND output.address = address.id
+            )
+        WHERE id IN (
+            SELECT output.address FROM output JOIN tx
+            ON output.tx = tx.id
+            WHERE tx.block = ?1
+        )",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "UPDATE address SET
+            tx_count = tx_count - (
+                SELECT count(*) FROM txs_by_address JOIN tx
+                ON txs_by_address.tx = tx.id
+                WHERE tx.block = ?1 AND txs_by_address.address = address.id
+            ),
+            first_block = (
+                SELECT tx.block FROM txs_by_address JOIN tx
+                ON txs_by_address.tx = tx.id
+                WHERE txs_by_address.address = address.id
+                AND tx.block IS NOT NULL AND tx.block != ?1
+                ORDER BY tx.id LIMIT 1
+            ),
+            last_block = (
+                SELECT tx.block FROM txs_by_address JOIN tx
+                ON txs_by_address.tx = tx.id
+                WHERE txs_by_address.address = address.id
+                AND tx.block IS NOT NULL AND tx.block != ?1
+                ORDER BY tx.id DESC LIMIT 1
+            )
+        WHERE id IN (
+            SELECT txs_by_address.address FROM txs_by_address JOIN tx
+            ON txs_by_address.tx = tx.id
+            WHERE tx.block = ?1
+        )",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "UPDATE output SET spent_by = NULL
+        WHERE spent_by IN (SELECT id FROM tx WHERE block = ?1)",
+        params![hash],
+    )?;
+
+    // submitted transactions of the block are pending again
+    conn.execute(
+        "UPDATE pending_tx SET block = NULL WHERE block = ?1",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "DELETE FROM txs_by_address
+        WHERE tx IN (SELECT id FROM tx WHERE block = ?1)",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "DELETE FROM input
+        WHERE tx IN (SELECT id FROM tx WHERE block = ?1)",
+        params![hash],
+    )?;
+
+    conn.execute(
+        "DELETE FROM output
+        WHERE tx IN (SELECT id FROM tx WHERE block = ?1)",
+        params![hash],
+    )?;
+
+    conn.execute("DELETE FROM tx WHERE block = ?1", params![hash])?;
+
+    info!("Reverted block {}", hash);
+    Ok(())
+}
+
+/// Parse a block hash read from the database.
+fn parse_hash(hash: &str) -> crate::types::Result<HeaderHash> {
+    HeaderHash::from_str(hash)
+        .map_err(|e| Error::DecodeError(format!("block hash {}", hash), format!("{:?}", e)))
+}
+
+pub fn last_applied_block(conn: &Connection) -> crate::types::Result<Option<HeaderHash>> {
+    let last_block: Option<String> = match conn.query_row(
+        "SELECT block FROM last_block WHERE id = 0",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    ) {
+        Ok(string) => Some(string),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into()),
+    };
+    last_block.map(|hash| parse_hash(&hash)).transpose()
+}
+
+pub fn next_block(
+    conn: &Connection,
+    block: HeaderHash,
+) -> crate::types::Result<Option<HeaderHash>> {
+    let result: Option<String> = conn.query_row(
+        "SELECT next FROM block WHERE id = ?1",
+        params![format!("{}", block)],
+        |row| row.get(0),
+    )?;
+
+    result.map(|h| parse_hash(&h)).transpose()
+}
+
+fn is_indexed(conn: &Connection, block: &HeaderHash) -> rusqlite::Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT count(*) FROM block WHERE id = ?1",
+        params![format!("{}", block)],
+        |row| row.get(0),
+    )?;
+
+    Ok(count > 0)
+}
+
+/// The applied blocks following `from`, oldest first. `None` if `from` is not
+/// in the index, as when it was rolled back.
+pub fn applied_after(
+    conn: &Connection,
+    from: &HeaderHash,
+) -> crate::types::Result<Option<Vec<HeaderHash>>> {
+    if !is_indexed(conn, from)? {
+        return Ok(None);
+    }
+
+    let last = match last_applied_block(conn)? {
+        Some(last) => last,
+        None => return Ok(Some(vec![])),
+    };
+
+    let mut blocks = vec![];
+    let mut cursor = from.clone();
+    while cursor != last {
+        match next_block(conn, cursor)? {
+            Some(next) => {
+                blocks.push(next.clone());
+                cursor = next;
+            }
+            // `from` is not applied yet
+            None => return Ok(Some(vec![])),
+        }
+    }
+
+    Ok(Some(blocks))
+}
+
+/// Extend the block index up to `to`.
+///
+/// The chain is walked back from `to` until a block already in the index is
+/// found. If that block is not the head of the index the bridge switched to a
+/// fork: the blocks after the common ancestor are dropped from the index, and
+/// the ones already applied are reverted. Returns the reverted blocks, newest
+/// first.
+///
+/// Nothing is committed here, so callers can apply the new branch in the same
+/// transaction.
+pub fn update_block_index<F>(
+    conn: &Connection,
+    to: HeaderHash,
+    get_previous: F,
+) -> crate::types::Result<Vec<HeaderHash>>
+where
+    F: Fn(HeaderHash) -> std::result::Result<HeaderHash, crate::types::Error>,
+{
+    info!("Updating block index");
+
+    // an empty index means the initial import was never run
+    let _head: String = conn.query_row(
+        "SELECT id FROM block WHERE next IS NULL",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    let mut branch = vec![];
+    let mut cursor = to;
+
+    let ancestor = loop {
+        if is_indexed(conn, &cursor)? {
+            break cursor;
+        }
+
+        let previous = get_previous(cursor.clone())?;
+        branch.push(cursor);
+        cursor = previous;
+    };
+
+    let mut orphaned = vec![];
+    let mut next = next_block(conn, ancestor.clone())?;
+    while let Some(hash) = next {
+        next = next_block(conn, hash.clone())?;
+        orphaned.push(hash);
+    }
+
+    let reverted = if orphaned.is_empty() {
+        vec![]
+    } else {
+        rollback(conn, &ancestor, &orphaned)?
+    };
+
+    let mut previous = ancestor;
+    for hash in branch.into_iter().rev() {
+        info!("Inserting block {}", hash);
+
+        conn.execute(
+            "UPDATE block SET next = ?2 WHERE id = ?1",
+            params![format!("{}", previous), format!("{}", hash)],
+        )?;
+
+        conn.execute(
+            "insert or replace into block(id, next)
+            values (?1, NULL)
+            ",
+            params![format!("{}", hash)],
+        )?;
+
+        previous = hash;
+    }
+
+    Ok(reverted)
+}
+
+/// Drop the `orphaned` blocks, which follow `ancestor` in the index, reverting
+/// the ones that were already applied.
+fn rollback(
+    conn: &Connection,
+    ancestor: &HeaderHash,
+    orphaned: &[HeaderHash],
+) -> crate::types::Result<Vec<HeaderHash>> {
+    warn!(
+        "Fork detected, rolling back {} blocks to {}",
+        orphaned.len(),
+        ancestor
+    );
+
+    let mut reverted = vec![];
+
+    let applied = match last_applied_block(conn)? {
+        Some(last) => orphaned.iter().position(|hash| *hash == last),
+        None => None,
+    };
+
+    if let Some(position) = applied {
+        for hash in orphaned[..=position].iter().rev() {
+            queue_reverted(conn, hash)?;
+            revert_block(conn, hash)?;
+            reverted.push(hash.clone());
+        }
+
+        conn.execute(
+            "insert or replace into last_block(id, block)
+            values (0, ?1)",
+            params![format!("{}", ancestor)],
+        )?;
+
+        // the blocks of the new branch have to be scanned again
+        conn.execute(
+            "UPDATE webhook SET notified_height = min(
+                notified_height,
+                coalesce((SELECT height FROM block WHERE id = ?1), notified_height)
+            )",
+            params![format!("{}", ancestor)],
+        )?;
+    }
+
+    for hash in orphaned {
+        conn.execute(
+            "DELETE FROM block WHERE id = ?1",
+            params![format!("{}", hash)],
+        )?;
+    }
+
+    conn.execute(
+        "UPDATE block SET next = NULL WHERE id = ?1",
+        params![format!("{}", ancestor)],
+    )?;
+
+    Ok(reverted)
+}
+
+/// Height up to which the transactions are `confirmations` blocks deep, the
+/// block of a transaction counting as its first confirmation. -1 while no
+/// block is applied, `None` if the height of the last applied block is not
+/// recorded (see `backfill-block-metadata`).
+fn confirmed_height(conn: &Connection, confirmations: u32) -> rusqlite::Result<Option<i64>> {
+    match conn.query_row(
+        "SELECT block.id, block.height FROM last_block LEFT JOIN block
+        ON last_block.block = block.id
+        WHERE last_block.id = 0",
+        rusqlite::NO_PARAMS,
+        |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<i64>>(1)?)),
+    ) {
+        // rolled back to the genesis
+        Ok((None, _)) => Ok(Some(-1)),
+        Ok((Some(_), height)) => Ok(height.map(|height| height + 1 - i64::from(confirmations))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Some(-1)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Register a webhook for `addresses`. Only the transactions confirmed from
+/// now on are notified to it.
+pub fn insert_webhook(
+    conn: &Connection,
+    url: &str,
+    secret: &str,
+    confirmations: u32,
+    addresses: &[String],
+) -> crate::types::Result<Webhook> {
+    // from -1 the whole history would be notified
+    let notified_height = match confirmed_height(conn, confirmations)? {
+        Some(height) => height,
+        None => {
+            return Err(Error::InvariantViolation(
+                "the height of the last applied block is unknown, \
+                 run backfill-block-metadata before registering webhooks"
+                    .to_string(),
+            ))
+        }
+    };
+
+    conn.execute(
+        "insert into webhook (id, url, secret, confirmations, notified_height)
+        values (NULL, ?1, ?2, ?3, ?4)",
+        params![url, secret, confirmations, notified_height],
+    )?;
+
+    let id = conn.last_insert_rowid();
+
+    for address in addresses {
+        conn.execute(
+            "insert or ignore into webhook_address (id, webhook, address)
+            values (NULL, ?1, ?2)",
+            params![id, address],
+        )?;
+    }
+
+    webhook(conn, id)
+}
+
+pub fn webhook(conn: &Connection, id: i64) -> crate::types::Result<Webhook> {
+    let (url, confirmations) = match conn.query_row(
+        "SELECT url, confirmations FROM webhook WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ) {
+        Ok(webhook) => webhook,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            return Err(Error::NotFound(format!("webhook {}", id)))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut stmt =
+        conn.prepare("SELECT address FROM webhook_address WHERE webhook = ?1 ORDER BY address")?;
+    let addresses = stmt
+        .query_map(params![id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(Webhook {
+        id,
+        url,
+        confirmations,
+        addresses,
+    })
+}
+
+pub fn webhooks(conn: &Connection) -> crate::types::Result<Vec<Webhook>> {
+    let mut stmt = conn.prepare("SELECT id FROM webhook ORDER BY id")?;
+    let ids = stmt
+        .query_map(rusqlite::NO_PARAMS, |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+    ids.into_iter().map(|id| webhook(conn, id)).collect()
+}
+
+/// Drop a webhook with its addresses and the payloads not delivered yet.
+pub fn delete_webhook(conn: &Connection, id: i64) -> crate::types::Result<()> {
+    conn.execute("DELETE FROM webhook_delivery WHERE webhook = ?1", params![id])?;
+    conn.execute("DELETE FROM webhook_notification WHERE webhook = ?1", params![id])?;
+    conn.execute("DELETE FROM webhook_address WHERE webhook = ?1", params![id])?;
+
+    if conn.execute("DELETE FROM webhook WHERE id = ?1", params![id])? == 0 {
+        return Err(Error::NotFound(format!("webhook {}", id)));
+    }
+
+    Ok(())
+}
+
+fn queue_delivery(conn: &Connection, payload: &WebhookPayload) -> rusqlite::Result<()> {
+    conn.execute(
+        "insert into webhook_delivery (id, webhook, payload, attempts, next_attempt)
+        values (NULL, ?1, ?2, 0, cast(strftime('%s', 'now') as integer))",
+        params![payload.webhook, serde_json::to_string(payload).unwrap()],
+    )?;
+
+    Ok(())
+}
+
+/// Group rows of (webhook, txid, block, height, address), sorted by
+/// transaction, into one payload per transaction.
+fn group_payloads<I>(event: WebhookEvent, rows: I) -> rusqlite::Result<Vec<WebhookPayload>>
+where
+    I: Iterator<Item = rusqlite::Result<(i64, String, String, Option<i64>, String)>>,
+{
+    let mut payloads: Vec<WebhookPayload> = vec![];
+
+    for row in rows {
+        let (webhook, txid, block, height, address) = row?;
+
+        match payloads.last_mut() {
+            Some(ref mut last) if last.webhook == webhook && last.txid == txid => {
+                last.addresses.push(address)
+            }
+            _ => payloads.push(WebhookPayload {
+                event,
+                webhook,
+                txid,
+                block,
+                height,
+                addresses: vec![address],
+            }),
+        }
+    }
+
+    Ok(payloads)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cardano::address::ExtendedAddr;
-    use cardano::coin::Coin;
-    use cardano::hash;
-    use cardano::util::base58;
-    use cardano::util::try_from_slice::TryFromSlice;
-    use std::collections::BTreeMap;
-    use std::str::FromStr;
+/// Queue a `confirmed` payload for every transaction which reached the depth
+/// of a webhook watching it since the last call. Returns the number of
+/// payloads queued.
+pub fn queue_confirmed(conn: &Connection) -> crate::types::Result<usize> {
+    let mut stmt = conn.prepare("SELECT id, confirmations, notified_height FROM webhook")?;
+    let webhooks = stmt
+        .query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?, row.get::<_, i64>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut queued = 0;
+
+    for (webhook, confirmations, notified_height) in webhooks {
+        let confirmed = match confirmed_height(conn, confirmations)? {
+            Some(height) => height,
+            None => {
+                error!(
+                    "The height of the last applied block is unknown, \
+                     no webhook is notified until backfill-block-metadata runs"
+                );
+                return Ok(0);
+            }
+        };
+        if confirmed <= notified_height {
+            continue;
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT webhook_address.webhook, tx.txid, tx.block, block.height,
+                webhook_address.address
+            FROM webhook_address JOIN address
+            ON webhook_address.address = address.address
+            JOIN txs_by_address
+            ON address.id = txs_by_address.address
+            JOIN tx
+            ON txs_by_address.tx = tx.id
+            JOIN block
+            ON tx.block = block.id
+            WHERE webhook_address.webhook = ?1 AND block.height > ?2 AND block.height <= ?3
+            ORDER BY block.height, tx.index_in_block, webhook_address.address
+        ",
+        )?;
+
+        let rows = stmt.query_map(params![webhook, notified_height, confirmed], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        })?;
+
+        for payload in group_payloads(WebhookEvent::Confirmed, rows)? {
+            let inserted = conn.execute(
+                "insert or ignore into webhook_notification (id, webhook, txid, block)
+                values (NULL, ?1, ?2, ?3)",
+                params![webhook, payload.txid, payload.block],
+            )?;
+
+            if inserted > 0 {
+                queue_delivery(conn, &payload)?;
+                queued += 1;
+            }
+        }
+
+        conn.execute(
+            "UPDATE webhook SET notified_height = ?2 WHERE id = ?1",
+            params![webhook, confirmed],
+        )?;
+    }
+
+    Ok(queued)
+}
+
+/// Queue a `reverted` payload for the transactions of `block` which were
+/// notified as confirmed. Runs before the block is reverted, while its
+/// transactions are still indexed.
+fn queue_reverted(conn: &Connection, block: &HeaderHash) -> rusqlite::Result<()> {
+    let hash = format!("{}", block);
+
+    let mut stmt = conn.prepare(
+        "SELECT webhook_notification.webhook, tx.txid, tx.block, block.height,
+            webhook_address.address
+        FROM webhook_notification JOIN tx
+        ON webhook_notification.txid = tx.txid
+        LEFT JOIN block
+        ON tx.block = block.id
+        JOIN txs_by_address
+        ON tx.id = txs_by_address.tx
+        JOIN address
+        ON txs_by_address.address = address.id
+        JOIN webhook_address
+        ON webhook_address.webhook = webhook_notification.webhook
+            AND webhook_address.address = address.address
+        WHERE webhook_notification.block = ?1
+        ORDER BY webhook_notification.webhook, tx.index_in_block, webhook_address.address
+    ",
+    )?;
+
+    let rows = stmt.query_map(params![hash], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    })?;
+
+    for payload in group_payloads(WebhookEvent::Reverted, rows)? {
+        queue_delivery(conn, &payload)?;
+    }
+
+    conn.execute(
+        "DELETE FROM webhook_notification WHERE block = ?1",
+        params![hash],
+    )?;
+
+    Ok(())
+}
+
+/// The payloads due for a delivery attempt, at most `limit`. Only the oldest
+/// payload of each webhook is returned, so they are delivered in order.
+pub fn due_deliveries(conn: &Connection, limit: u32) -> rusqlite::Result<Vec<Delivery>> {
+    let mut stmt = conn.prepare(
+        "SELECT delivery.id, webhook.url, webhook.secret, delivery.payload, delivery.attempts
+        FROM webhook_delivery AS delivery JOIN webhook
+        ON delivery.webhook = webhook.id
+        WHERE delivery.id = (
+            SELECT min(id) FROM webhook_delivery WHERE webhook = delivery.webhook
+        )
+        AND delivery.next_attempt <= cast(strftime('%s', 'now') as integer)
+        ORDER BY delivery.id
+        LIMIT ?1
+    ",
+    )?;
+
+    let deliveries = stmt.query_map(params![limit], |row| {
+        Ok(Delivery {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            secret: row.get(2)?,
+            payload: row.get(3)?,
+            attempts: row.get(4)?,
+        })
+    })?;
+
+    deliveries.collect()
+}
+
+pub fn delivery_succeeded(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM webhook_delivery WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Record a failed attempt, the next one being due in `retry_in` seconds.
+pub fn delivery_failed(
+    conn: &Connection,
+    id: i64,
+    retry_in: u64,
+    error: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE webhook_delivery SET
+            attempts = attempts + 1,
+            next_attempt = cast(strftime('%s', 'now') as integer) + ?2,
+            last_error = ?3
+        WHERE id = ?1",
+        params![id, retry_in as i64, error],
+    )?;
+
+    Ok(())
+}
+
+/// Whether the output `pointer` is spent, None if it is not indexed.
+pub fn output_spent(conn: &Connection, pointer: &TxoPointer) -> rusqlite::Result<Option<bool>> {
+    match conn.query_row(
+        "SELECT output.spent_by IS NOT NULL
+        FROM tx JOIN output
+        ON tx.id = output.tx
+        WHERE tx.txid = ?1 AND output.offset = ?2",
+        params![format!("{}", pointer.id), pointer.index],
+        |row| row.get(0),
+    ) {
+        Ok(spent) => Ok(Some(spent)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Record a transaction sent to the bridge, pending until a block with it is
+/// applied. Submitting it again restarts its timeout.
+pub fn record_pending(conn: &Connection, txid: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "insert or replace into pending_tx (txid, submitted_at, block)
+        values (?1, cast(strftime('%s', 'now') as integer),
+            (SELECT block FROM tx WHERE txid = ?1))",
+        params![txid],
+    )?;
+
+    Ok(())
+}
+
+/// Forget the submissions whose status can't change anymore: the ones in a
+/// block deeper than `stability_depth`, and the ones expired, `timeout`
+/// seconds after their submission, for `retention` seconds. Until then the
+/// expired ones are reported as such. Their transactions are then told apart
+/// by the `tx` table alone, as if they were not submitted here.
+pub fn prune_pending(
+    conn: &Connection,
+    timeout: u64,
+    retention: u64,
+    stability_depth: usize,
+) -> rusqlite::Result<usize> {
+    let mut pruned = conn.execute(
+        "DELETE FROM pending_tx
+        WHERE block IS NULL AND submitted_at + ?1 < cast(strftime('%s', 'now') as integer)",
+        params![(timeout + retention) as i64],
+    )?;
+
+    if let Some(height) = last_applied_height(conn)? {
+        pruned += conn.execute(
+            "DELETE FROM pending_tx
+            WHERE block IN (SELECT id FROM block WHERE height < ?1)",
+            params![height - stability_depth as i64],
+        )?;
+    }
+
+    Ok(pruned)
+}
+
+/// The status of a submitted transaction, which expires when it is still not
+/// in a block `timeout` seconds after its submission. Transactions indexed
+/// without being submitted here are confirmed.
+pub fn submission(conn: &Connection, txid: &str, timeout: u64) -> crate::types::Result<Submission> {
+    match conn.query_row(
+        "SELECT submitted_at, block,
+            submitted_at + ?2 < cast(strftime('%s', 'now') as integer)
+        FROM pending_tx WHERE txid = ?1",
+        params![txid, timeout as i64],
+        |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        },
+    ) {
+        Ok((submitted_at, block, expired)) => {
+            let status = match (&block, expired) {
+                (Some(_), _) => SubmissionStatus::Confirmed,
+                (None, true) => SubmissionStatus::Expired,
+                (None, false) => SubmissionStatus::Pending,
+            };
+
+            return Ok(Submission {
+                txid: txid.to_string(),
+                status,
+                submitted_at: Some(submitted_at),
+                block,
+            });
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    match conn.query_row(
+        "SELECT block FROM tx WHERE txid = ?1",
+        params![txid],
+        |row| row.get(0),
+    ) {
+        Ok(block) => Ok(Submission {
+            txid: txid.to_string(),
+            status: SubmissionStatus::Confirmed,
+            submitted_at: None,
+            block,
+        }),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            Err(Error::NotFound(format!("transaction {}", txid)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Instant};
+pub fn last_imported_epoch(conn: &Connection) -> rusqlite::Result<Option<EpochId>> {
+    match conn.query_row(
+        "SELECT epoch FROM imported_epoch WHERE id = 0",
+        rusqlite::NO_PARAMS,
+        |row| row.get::<_, i64>(0),
+    ) {
+        Ok(epoch) => Ok(Some(epoch as EpochId)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `block` is applied, which records its height.
+fn block_applied(conn: &Connection, block: &HeaderHash) -> rusqlite::Result<bool> {
+    match conn.query_row(
+        "SELECT height IS NOT NULL FROM block WHERE id = ?1",
+        params![format!("{}", block)],
+        |row| row.get(0),
+    ) {
+        Ok(applied) => Ok(applied),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn record_imported_epoch(conn: &Connection, epoch: EpochId) -> rusqlite::Result<()> {
+    conn.execute(
+        "insert or replace into imported_epoch(id, epoch)
+        values (0, ?1)",
+        params![epoch as i64],
+    )?;
+    Ok(())
+}
+
+/// How the initial import spreads its work: `workers` threads fetch and
+/// decode epochs, at most `max_pending_epochs` of them being held in memory
+/// while waiting to be applied.
+#[derive(Clone, Debug)]
+pub struct Pipeline {
+    pub workers: usize,
+    pub max_pending_epochs: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            workers: 4,
+            max_pending_epochs: 8,
+        }
+    }
+}
+
+fn invalid_pack<E: std::fmt::Debug>(id: EpochId, error: E) -> Error {
+    Error::DecodeError(format!("pack of epoch {}", id), format!("{:?}", error))
+}
+
+/// The blocks of a pack, each along with its encoding.
+fn decode_epoch(id: EpochId, pack: &[u8]) -> crate::types::Result<Vec<(Block, Vec<u8>)>> {
+    let mut reader = Reader::init(pack).map_err(|e| invalid_pack(id, e))?;
+    let mut blocks = vec![];
+
+    while let Some(b) = reader.next_block().map_err(|e| invalid_pack(id, e))? {
+        let what = format!("block {} of epoch {}", blocks.len(), id);
+        let block = block::RawBlock(b.clone())
+            .decode()
+            .map_err(|e| Error::DecodeError(what, format!("{}", e)))?;
+        blocks.push((block, b));
+    }
+
+    Ok(blocks)
+}
+
+/// Import the stable epochs following the last one imported, up to
+/// `first_unstable_epoch`. Every epoch is committed along with its number, so
+/// an interrupted import resumes from the next one.
+///
+/// Epochs are fetched and decoded ahead by the workers of `pipeline`, and
+/// applied in order on the calling thread.
+pub fn sync_from_epochs<F>(
+    conn: &mut Connection,
+    genesis_prev: &HeaderHash,
+    first_unstable_epoch: EpochId,
+    pipeline: &Pipeline,
+    get_epoch: F,
+) -> crate::types::Result<()>
+where
+    F: Fn(EpochId) -> crate::types::Result<Vec<u8>> + Send + Sync + 'static,
+{
+    let first_epoch = last_imported_epoch(conn)?.map_or(0, |epoch| epoch + 1);
+
+    if first_epoch >= first_unstable_epoch {
+        info!("All stable epochs already imported");
+        return Ok(());
+    }
+
+    // every job comes with the channel its epoch is sent back on, so a
+    // worker failing to send it shows as the end of that channel
+    type Job = (EpochId, mpsc::Sender<crate::types::Result<Vec<(Block, Vec<u8>)>>>);
+
+    let get_epoch = Arc::new(get_epoch);
+    let (jobs, job_receiver) = mpsc::channel::<Job>();
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+    for _ in 0..pipeline.workers.max(1) {
+        let get_epoch = get_epoch.clone();
+        let job_receiver = job_receiver.clone();
+
+        thread::spawn(move || loop {
+            let (id, result) = match job_receiver.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+
+            let _ = result.send((*get_epoch)(id).and_then(|pack| decode_epoch(id, &pack)));
+        });
+    }
+
+    // an epoch is only requested once the one `max_pending_epochs` before it
+    // is applied, which bounds the decoded epochs held in memory
+    let mut next_job = first_epoch;
+    let mut pending = VecDeque::new();
+    let mut request_next = |pending: &mut VecDeque<_>| {
+        if next_job < first_unstable_epoch {
+            let (result, receiver) = mpsc::channel();
+            // the workers only stop once the jobs are dropped
+            let _ = jobs.send((next_job, result));
+            pending.push_back((next_job, receiver));
+            next_job += 1;
+        }
+    };
+
+    for _ in 0..pipeline.max_pending_epochs.max(1) {
+        request_next(&mut pending);
+    }
+
+    while let Some((i, receiver)) = pending.pop_front() {
+        let blocks = receiver.recv().map_err(|_| {
+            Error::InvariantViolation(format!("the worker fetching epoch {} stopped", i))
+        })??;
+
+        import_epoch(conn, genesis_prev, i, blocks)?;
+        request_next(&mut pending);
+    }
+
+    Ok(())
+}
+
+/// Apply the blocks of epoch `i` and record it as imported, in a single
+/// transaction.
+///
+/// Blocks already applied, by the sync loop or by an import of a database
+/// that doesn't record its epochs, are skipped: the import starts with the
+/// block following the last applied one. Any other block that doesn't follow
+/// it is an invariant violation, and the epoch isn't recorded.
+fn import_epoch(
+    conn: &mut Connection,
+    genesis_prev: &HeaderHash,
+    i: EpochId,
+    blocks: Vec<(Block, Vec<u8>)>,
+) -> crate::types::Result<()> {
+    let transaction = conn.transaction()?;
+
+    info!("Epoch: {}", i);
+
+    let now = Instant::now();
+    let mut last_applied = last_applied_block(&transaction)?;
+    let mut skipped = 0;
+
+    for (block, raw) in blocks {
+        let header = block.header();
+
+        if let Some(ref last) = last_applied {
+            if *last != header.previous_header() {
+                let hash = header.compute_hash();
+                if block_applied(&transaction, &hash)? {
+                    skipped += 1;
+                    continue;
+                }
+
+                return Err(Error::InvariantViolation(format!(
+                    "block {} of epoch {} doesn't follow the last applied block {}",
+                    hash, i, last
+                )));
+            }
+        }
+
+        apply_block(&transaction, genesis_prev, &block, &raw)?;
+
+        transaction.execute(
+            "UPDATE block SET next = ?1 WHERE id = ?2",
+            params![
+                format!("{}", header.compute_hash()),
+                format!("{}", header.previous_header())
+            ],
+        )?;
+
+        last_applied = Some(header.compute_hash());
+    }
+
+    record_imported_epoch(&transaction, i)?;
+
+    transaction.commit()?;
+
+    if skipped > 0 {
+        info!("Skipped {} blocks already applied", skipped);
+    }
+    info!("Finished epoch {} in {}", i, now.elapsed().as_millis());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cardano::address::ExtendedAddr;
+    use cardano::block::date::EpochSlotId;
+    use cardano::coin::Coin;
+    use cardano::hash;
+    use cardano::tx::TxWitness;
+    use cardano::util::base58;
+    use cardano::util::try_from_slice::TryFromSlice;
+    use crate::types::DEFAULT_PAGE_LIMIT;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    fn date() -> BlockDate {
+        BlockDate::Normal(EpochSlotId {
+            epoch: 1,
+            slotid: 2,
+        })
+    }
+
+    fn fixture() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/synthetic")
+    }
+
+    /// The packed blocks of epoch `id` of the chain of `fixture()`.
+    fn fixture_pack(id: EpochId) -> Vec<u8> {
+        std::fs::read(fixture().join("epoch").join(id.to_string())).unwrap()
+    }
+
+    /// Prepare `conn` with the initial utxos of the chain of `fixture()`,
+    /// returning the genesis it starts from and the number of those utxos.
+    fn prepare_fixture(conn: &mut Connection) -> (HeaderHash, usize) {
+        prepare_schema(conn).unwrap();
+
+        let genesis = std::fs::read(fixture().join("genesis.json")).unwrap();
+        let genesis_data = exe_common::genesisdata::parse::parse(&genesis);
+        let chain_state = cardano::block::ChainState::new(&genesis_data);
+        apply_initial_state(conn, &chain_state.utxos).unwrap();
+
+        (genesis_data.genesis_prev, chain_state.utxos.len())
+    }
+
+    fn to_cbor<T: cbor_event::se::Serialize>(value: &T) -> rusqlite::Result<Vec<u8>> {
+        let mut serializer = cbor_event::se::Serializer::new_vec();
+        serializer
+            .serialize(value)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(serializer.finalize())
+    }
+
+    #[test]
+    fn test_block_index_update() {
+        let conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let next = None;
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+        let hash2 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91844",
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert or replace into block(id, next)
+            values (?1, ?2)
+            ",
+            params![
+                format!("{}", initial),
+                next.map(|h: HeaderHash| format!("{}", h))
+            ],
+        )
+        .unwrap();
+
+        update_block_index(&conn, hash2.clone(), |h| {
+            if h == hash2 {
+                Ok(hash1.clone())
+            } else {
+                Ok(initial.clone())
+            }
+        })
+        .unwrap();
+
+        let second: String = conn
+            .query_row(
+                "SELECT next FROM block WHERE id = ?1",
+                params![format!("{}", initial)],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(second == format!("{}", hash1));
+
+        let third: String = conn
+            .query_row(
+                "SELECT next FROM block WHERE id = ?1",
+                params![format!("{}", hash1)],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(third == format!("{}", hash2));
+
+        let fourth: Option<String> = conn
+            .query_row(
+                "SELECT next FROM block WHERE id = ?1",
+                params![format!("{}", hash2)],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(fourth.is_none());
+    }
+
+    #[test]
+    fn test_blocks_to_apply() {
+        let conn = Connection::open(":memory:").unwrap();
+
+        prepare_schema(&conn).unwrap();
+
+        let next = None;
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+
+        let hash2 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91844",
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert or replace into block(id, next)
+                values (?1, ?2)
+                ",
+            params![
+                format!("{}", initial),
+                next.map(|h: HeaderHash| format!("{}", h))
+            ],
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert or replace into last_block(id, block)
+                values (0, ?1)
+                ",
+            params![format!("{}", initial)],
+        )
+        .unwrap();
+
+        update_block_index(&conn, hash2.clone(), |h| {
+            if h == hash2 {
+                Ok(hash1.clone())
+            } else {
+                Ok(initial.clone())
+            }
+        })
+        .unwrap();
+
+        let block1 = last_applied_block(&conn).unwrap().unwrap();
+        assert!(block1 == initial);
+        let block2 = next_block(&conn, block1).unwrap().unwrap();
+        assert!(block2 == hash1);
+        let block3 = next_block(&conn, block2).unwrap().unwrap();
+        assert!(block3 == hash2);
+    }
+
+    #[test]
+    fn test_resume_import() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        assert!(last_imported_epoch(&conn).unwrap().is_none());
+
+        record_imported_epoch(&conn, 4).unwrap();
+        assert!(last_imported_epoch(&conn).unwrap() == Some(4));
+
+        let genesis_prev = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+        sync_from_epochs(&mut conn, &genesis_prev, 5, &Pipeline::default(), |epoch| {
+            panic!("epoch {} imported again", epoch)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_import() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        let (genesis_prev, initial_utxos) = prepare_fixture(&mut conn);
+
+        let packs: Vec<Vec<u8>> = (0..3).map(fixture_pack).collect();
+        let tip = decode_epoch(2, &packs[2]).unwrap().last().unwrap().0.header().compute_hash();
+
+        let pipeline = Pipeline {
+            workers: 2,
+            max_pending_epochs: 2,
+        };
+
+        // the bridge fails on the third epoch, after the first two
+        let interrupted = packs.clone();
+        let result = sync_from_epochs(&mut conn, &genesis_prev, 3, &pipeline, move |epoch| {
+            if epoch == 2 {
+                return Err(Error::NotFound(format!("epoch {}", epoch)));
+            }
+            Ok(interrupted[epoch as usize].clone())
+        });
+        assert!(result.is_err());
+        assert!(last_imported_epoch(&conn).unwrap() == Some(1));
+
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let resumed = fetched.clone();
+        sync_from_epochs(&mut conn, &genesis_prev, 3, &pipeline, move |epoch| {
+            resumed.lock().unwrap().push(epoch);
+            Ok(packs[epoch as usize].clone())
+        })
+        .unwrap();
+
+        // only the epoch left is fetched, and its blocks follow the others
+        assert!(*fetched.lock().unwrap() == vec![2]);
+        assert!(last_imported_epoch(&conn).unwrap() == Some(2));
+        assert!(last_applied_block(&conn).unwrap() == Some(tip));
+
+        let transactions: i64 = conn
+            .query_row("SELECT count(*) FROM tx", rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(transactions == initial_utxos as i64 + 6);
+    }
+
+    #[test]
+    fn test_import_not_following() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        let (genesis_prev, _) = prepare_fixture(&mut conn);
+
+        let epoch = |id: EpochId| decode_epoch(id, &fixture_pack(id)).unwrap();
+
+        // the first block follows the genesis, at height 0
+        let blocks = epoch(0).len() as i64;
+        import_epoch(&mut conn, &genesis_prev, 0, epoch(0)).unwrap();
+        let tip = last_applied_block(&conn).unwrap();
+        assert!(last_applied_height(&conn).unwrap() == Some(blocks - 1));
+
+        // a block whose parent is neither indexed nor the genesis has none
+        let other = Connection::open(":memory:").unwrap();
+        prepare_schema(&other).unwrap();
+        let (block, raw) = epoch(1).remove(0);
+        update_block_metadata(&other, &genesis_prev, &block, raw.len()).unwrap();
+        assert!(!block_applied(&other, &block.header().compute_hash()).unwrap());
+
+        // the blocks of epoch 1 are missing, so epoch 2 can't be applied
+        match import_epoch(&mut conn, &genesis_prev, 2, epoch(2)) {
+            Err(Error::InvariantViolation(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(last_imported_epoch(&conn).unwrap() == Some(0));
+        assert!(last_applied_block(&conn).unwrap() == tip);
+
+        // the blocks already applied are skipped
+        import_epoch(&mut conn, &genesis_prev, 0, epoch(0)).unwrap();
+        assert!(last_applied_block(&conn).unwrap() == tip);
+        import_epoch(&mut conn, &genesis_prev, 1, epoch(1)).unwrap();
+        import_epoch(&mut conn, &genesis_prev, 2, epoch(2)).unwrap();
+        assert!(last_imported_epoch(&conn).unwrap() == Some(2));
+    }
+
+    #[test]
+    fn test_import_pipeline() {
+        let database = std::env::temp_dir().join(format!(
+            "import-pipeline-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&database);
+
+        let mut conn = Connection::open(&database).unwrap();
+        let (genesis_prev, initial_utxos) = prepare_fixture(&mut conn);
+
+        let packs: Vec<Vec<u8>> = (0..3).map(fixture_pack).collect();
+        let tip = decode_epoch(2, &packs[2]).unwrap().last().unwrap().0.header().compute_hash();
+
+        let pipeline = Pipeline {
+            workers: 2,
+            max_pending_epochs: 2,
+        };
+        let max_pending = pipeline.max_pending_epochs as EpochId;
+
+        // reads what the import committed while it runs
+        let observer = Connection::open(&database).unwrap();
+        observer.busy_timeout(Duration::from_secs(10)).unwrap();
+        let observer = Mutex::new(observer);
+
+        sync_from_epochs(&mut conn, &genesis_prev, 3, &pipeline, move |epoch| {
+            let imported = last_imported_epoch(&observer.lock().unwrap()).unwrap();
+            if epoch >= max_pending {
+                assert!(
+                    imported >= Some(epoch - max_pending),
+                    "epoch {} requested with {:?} imported",
+                    epoch,
+                    imported
+                );
+            }
+
+            // the first epoch is fetched last, so the second one waits for it
+            if epoch == 0 {
+                thread::sleep(Duration::from_millis(200));
+            }
+
+            Ok(packs[epoch as usize].clone())
+        })
+        .unwrap();
+
+        assert!(last_imported_epoch(&conn).unwrap() == Some(2));
+        assert!(last_applied_block(&conn).unwrap() == Some(tip));
+
+        // no block was skipped for coming out of order
+        let transactions: i64 = conn
+            .query_row("SELECT count(*) FROM tx", rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(transactions == initial_utxos as i64 + 6);
+
+        let _ = std::fs::remove_file(&database);
+    }
+
+    #[test]
+    fn test_initial_state() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let bytes = base58::decode(addr_str).unwrap();
+        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        let value = 10000;
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address,
+                value: Coin::new(value).unwrap(),
+            },
+        );
+
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let address_rowid: i64 = conn
+            .query_row(
+                "SELECT rowid FROM address WHERE address=?1",
+                params![addr_str],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(address_rowid > 0);
+
+        let tx_rowid: i64 = conn
+            .query_row(
+                "SELECT rowid FROM tx WHERE txid=?1",
+                params![format!("{}", id)],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(tx_rowid > 0);
+
+        let output_rowid: i64 = conn
+            .query_row(
+                "SELECT rowid FROM output WHERE 
+                            tx=?1 AND
+                            address=?2 AND
+                            offset=?3 AND
+                            value=?4
+                        ",
+                params![tx_rowid, address_rowid, 0, value as i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(output_rowid > 0);
+
+        let tx_by_address: i64 = conn
+            .query_row(
+                "SELECT rowid FROM txs_by_address WHERE 
+                    tx=?1 AND
+                    address=?2
+                ",
+                params![tx_rowid, address_rowid],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(tx_by_address > 0);
+    }
+
+    #[test]
+    fn test_add_tx() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let bytes = base58::decode(addr_str).unwrap();
+        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        let value = 10000;
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(value).unwrap(),
+            },
+        );
+
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let mut tx = Tx::new();
+
+        let input = TxoPointer { id, index: 0 };
+
+        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
+        let address_dest =
+            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
+
+        let output = TxOut {
+            address: address_dest,
+            value: Coin::new(5000).unwrap(),
+        };
+
+        let rest = TxOut {
+            address: address.clone(),
+            value: Coin::new(5000).unwrap(),
+        };
+
+        tx.add_input(input);
+        tx.add_output(output);
+        tx.add_output(rest);
+
+        insert_tx(&conn, &initial, &date(), 0, tx.clone()).unwrap();
+
+        let tx_rowid: i64 = conn
+            .query_row(
+                "SELECT rowid FROM tx WHERE txid=?1",
+                params![format!("{}", tx.id())],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(tx_rowid == 2);
+
+        let output1_rowid: i64 = conn
+            .query_row(
+                "SELECT output.id FROM output join address WHERE 
+                    tx=?1 AND
+                    address.address=?2 AND
+                    offset=?3 AND
+                    value=?4
+                ",
+                params![tx_rowid, addr_dest_str, 0, 5000i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(output1_rowid > 0);
+
+        let output2_rowid: i64 = conn
+            .query_row(
+                "SELECT output.id FROM output join address WHERE 
+                    tx=?1 AND
+                    address.address=?2 AND
+                    offset=?3 AND
+                    value=?4
+                ",
+                params![tx_rowid, addr_str, 1, 5000i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(output2_rowid > 0);
+
+        let input_rowid: i64 = conn
+            .query_row(
+                "SELECT rowid FROM input WHERE 
+                    tx=?1 AND
+                    offset=?2
+                ",
+                params![tx_rowid, 0],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(input_rowid > 0);
+
+        let tx_by_address_1: i64 = conn
+            .query_row(
+                "SELECT txs_by_address.id FROM 
+                    txs_by_address join address
+                    on txs_by_address.address = address.id
+                WHERE 
+                    tx=?1 AND
+                    address.address=?2
+                ",
+                params![tx_rowid, addr_str],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(tx_by_address_1 > 0);
+
+        let tx_by_address_2: i64 = conn
+            .query_row(
+                "SELECT txs_by_address.id FROM 
+                    txs_by_address join address
+                    on txs_by_address.address = address.id
+                WHERE 
+                    tx=?1 AND
+                    address.address=?2
+                ",
+                params![tx_rowid, addr_dest_str],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(tx_by_address_2 > 0);
+
+        let inputs_of_tx = inputs(&conn, tx_rowid).unwrap();
+        assert!(inputs_of_tx.len() == 1);
+        assert!(inputs_of_tx[0].address == addr_str);
+        assert!(inputs_of_tx[0].value == 10000);
+
+        // as a migration leaves an input whose output is not indexed
+        conn.execute(
+            "UPDATE input SET address = NULL, value = NULL WHERE id = ?1",
+            params![input_rowid],
+        )
+        .unwrap();
+
+        match transaction(&conn, format!("{}", tx.id())) {
+            Err(Error::InvariantViolation(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
 
     #[test]
-    fn test_block_index_update() {
+    fn test_transactions_by_address() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let bytes = base58::decode(addr_str).unwrap();
+        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        let value = 10000;
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(value).unwrap(),
+            },
+        );
+
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let mut tx = Tx::new();
+
+        let input = TxoPointer { id, index: 0 };
+
+        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
+        let address_dest =
+            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
+
+        let output = TxOut {
+            address: address_dest.clone(),
+            value: Coin::new(5000).unwrap(),
+        };
+
+        let rest = TxOut {
+            address: address.clone(),
+            value: Coin::new(5000).unwrap(),
+        };
+
+        tx.add_input(input);
+        tx.add_output(output);
+        tx.add_output(rest);
+
+        insert_tx(&conn, &initial, &date(), 0, tx.clone()).unwrap();
+
+        let (transactions, next) =
+            transactions_of(&conn, &[address.clone()], &Page::default()).unwrap();
+
+        assert!(next.is_none());
+
+        let transaction1 = Transaction {
+            txid: format!("{}", id),
+            block: None,
+            epoch: None,
+            slot: None,
+            index_in_block: None,
+            inputs: vec![],
+            outputs: vec![Output {
+                value: 10000,
+                address: format!("{}", address.clone()),
+            }],
+            total_input: 0,
+            total_output: 10000,
+            fee: None,
+        };
+
+        assert!(transactions
+            .iter()
+            .any(|transaction| { transaction == &transaction1 }));
+
+        let transaction2 = Transaction {
+            txid: format!("{}", tx.id()),
+            block: Some(format!("{}", initial)),
+            epoch: Some(1),
+            slot: Some(2),
+            index_in_block: Some(0),
+            inputs: vec![Input {
+                id: format!("{}", id),
+                index: 0,
+                address: format!("{}", address.clone()),
+                value: 10000,
+            }],
+            outputs: vec![
+                Output {
+                    address: format!("{}", address_dest.clone()),
+                    value: 5000,
+                },
+                Output {
+                    address: format!("{}", address.clone()),
+                    value: 5000,
+                },
+            ],
+            total_input: 10000,
+            total_output: 10000,
+            fee: Some(0),
+        };
+
+        assert!(transactions
+            .iter()
+            .any(|transaction| { transaction == &transaction2 }));
+
+        let (merged, _) = transactions_of(
+            &conn,
+            &[address.clone(), address_dest.clone()],
+            &Page::default(),
+        )
+        .unwrap();
+
+        assert!(merged == vec![transaction1, transaction2]);
+
+        let mut addresses = addresses_of(&conn, &format!("{}", tx.id())).unwrap();
+        addresses.sort();
+        assert!(addresses == vec![addr_str.to_string(), addr_dest_str.to_string()]);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+
+        let fork = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91845",
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert into block(id, next) values (?1, ?2), (?2, NULL)",
+            params![format!("{}", initial), format!("{}", hash1)],
+        )
+        .unwrap();
+
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+
+        conn.execute(
+            "insert or replace into last_block(id, block) values (0, ?1)",
+            params![format!("{}", hash1)],
+        )
+        .unwrap();
+
+        let reverted = update_block_index(&conn, fork.clone(), |_| Ok(initial.clone())).unwrap();
+
+        assert!(reverted == vec![hash1.clone()]);
+        assert!(last_applied_block(&conn).unwrap().unwrap() == initial);
+        assert!(next_block(&conn, initial.clone()).unwrap().unwrap() == fork);
+        assert!(next_block(&conn, fork.clone()).unwrap().is_none());
+        assert!(!is_indexed(&conn, &hash1).unwrap());
+
+        let (transactions, _) =
+            transactions_of(&conn, &[address.clone()], &Page::default()).unwrap();
+        assert!(transactions.len() == 1);
+        assert!(transactions[0].txid == format!("{}", id));
+
+        let inputs: i64 = conn
+            .query_row("SELECT count(*) FROM input", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(inputs == 0);
+    }
+
+    #[test]
+    fn test_block_activity() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
+        let address_dest =
+            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let initial = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        )
+        .unwrap();
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+        let hash2 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91844",
+        )
+        .unwrap();
+        let unknown = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91845",
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert into block(id, next) values (?1, ?2), (?2, ?3), (?3, NULL)",
+            params![
+                format!("{}", initial),
+                format!("{}", hash1),
+                format!("{}", hash2)
+            ],
+        )
+        .unwrap();
+
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address_dest.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+
+        conn.execute(
+            "insert or replace into last_block(id, block) values (0, ?1)",
+            params![format!("{}", hash1)],
+        )
+        .unwrap();
+
+        let mut addresses = vec![addr_str.to_string(), addr_dest_str.to_string()];
+        addresses.sort();
+
+        assert!(
+            block_activity(&conn, &hash1).unwrap()
+                == BlockActivity {
+                    block: format!("{}", hash1),
+                    epoch: None,
+                    slot: None,
+                    transactions: vec![Activity {
+                        txid: format!("{}", tx.id()),
+                        addresses,
+                    }],
+                }
+        );
+        assert!(block_activity(&conn, &hash2).unwrap().transactions.is_empty());
+
+        assert!(applied_after(&conn, &initial).unwrap() == Some(vec![hash1.clone()]));
+        assert!(applied_after(&conn, &hash1).unwrap() == Some(vec![]));
+        // indexed but not applied yet
+        assert!(applied_after(&conn, &hash2).unwrap() == Some(vec![]));
+        assert!(applied_after(&conn, &unknown).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_webhooks() {
         let mut conn = Connection::open(":memory:").unwrap();
         prepare_schema(&conn).unwrap();
 
-        let next = None;
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
         let initial = HeaderHash::from_str(
             "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
         )
         .unwrap();
-
         let hash1 = HeaderHash::from_str(
             "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
         )
@@ -491,418 +3176,975 @@ mod tests {
             "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91844",
         )
         .unwrap();
+        let fork = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91845",
+        )
+        .unwrap();
 
         conn.execute(
-            "insert or replace into block(id, next)
-            values (?1, ?2)
-            ",
+            "insert into block(id, next, height) values (?1, ?2, 0), (?2, ?3, 1), (?3, NULL, 2)",
             params![
                 format!("{}", initial),
-                next.map(|h: HeaderHash| format!("{}", h))
+                format!("{}", hash1),
+                format!("{}", hash2)
             ],
         )
         .unwrap();
 
-        update_block_index(&mut conn, hash2.clone(), |h| {
-            if h == hash2 {
-                hash1.clone()
-            } else {
-                initial.clone()
-            }
-        })
-        .unwrap();
-
-        let second: String = conn
-            .query_row(
-                "SELECT next FROM block WHERE id = ?1",
-                params![format!("{}", initial)],
-                |row| row.get(0),
+        let set_last_block = |hash: &HeaderHash| {
+            conn.execute(
+                "insert or replace into last_block(id, block) values (0, ?1)",
+                params![format!("{}", hash)],
             )
             .unwrap();
+        };
 
-        assert!(second == format!("{}", hash1));
+        set_last_block(&initial);
 
-        let third: String = conn
-            .query_row(
-                "SELECT next FROM block WHERE id = ?1",
-                params![format!("{}", hash1)],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let registered = insert_webhook(
+            &conn,
+            "http://localhost/hook",
+            "secret",
+            2,
+            &[addr_str.to_string()],
+        )
+        .unwrap();
+        assert!(webhook(&conn, registered.id).unwrap() == registered);
+        assert!(webhooks(&conn).unwrap() == vec![registered]);
 
-        assert!(third == format!("{}", hash2));
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+
+        // in the last block, one confirmation only
+        set_last_block(&hash1);
+        assert!(queue_confirmed(&conn).unwrap() == 0);
+
+        set_last_block(&hash2);
+        assert!(queue_confirmed(&conn).unwrap() == 1);
+        assert!(queue_confirmed(&conn).unwrap() == 0);
+
+        update_block_index(&conn, fork.clone(), |_| Ok(initial.clone())).unwrap();
+
+        // the revert waits for the confirmation to be delivered
+        let deliveries = due_deliveries(&conn, 10).unwrap();
+        assert!(deliveries.len() == 1);
+        assert!(deliveries[0].url == "http://localhost/hook");
+
+        let confirmed: WebhookPayload = serde_json::from_str(&deliveries[0].payload).unwrap();
+        assert!(
+            confirmed
+                == WebhookPayload {
+                    event: WebhookEvent::Confirmed,
+                    webhook: 1,
+                    txid: format!("{}", tx.id()),
+                    block: format!("{}", hash1),
+                    height: Some(1),
+                    addresses: vec![addr_str.to_string()],
+                }
+        );
 
-        let fourth: Option<String> = conn
-            .query_row(
-                "SELECT next FROM block WHERE id = ?1",
-                params![format!("{}", hash2)],
-                |row| row.get(0),
-            )
+        delivery_succeeded(&conn, deliveries[0].id).unwrap();
+
+        let deliveries = due_deliveries(&conn, 10).unwrap();
+        assert!(deliveries.len() == 1);
+        let reverted: WebhookPayload = serde_json::from_str(&deliveries[0].payload).unwrap();
+        assert!(reverted.event == WebhookEvent::Reverted);
+        assert!(reverted.txid == format!("{}", tx.id()));
+
+        delivery_failed(&conn, deliveries[0].id, 60, "timeout").unwrap();
+        assert!(due_deliveries(&conn, 10).unwrap().is_empty());
+
+        let notified_height: i64 = conn
+            .query_row("SELECT notified_height FROM webhook", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
             .unwrap();
+        assert!(notified_height == 0);
 
-        assert!(fourth.is_none());
+        delete_webhook(&conn, 1).unwrap();
+        assert!(webhooks(&conn).unwrap().is_empty());
+        match webhook(&conn, 1) {
+            Err(Error::NotFound(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match delete_webhook(&conn, 1) {
+            Err(Error::NotFound(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // without the height of the last block, nothing is notified from -1
+        conn.execute(
+            "insert into block(id, next, height) values (?1, NULL, NULL)",
+            params![format!("{}", fork)],
+        )
+        .unwrap();
+        set_last_block(&fork);
+
+        match insert_webhook(&conn, "http://localhost/hook", "secret", 2, &[addr_str.to_string()]) {
+            Err(Error::InvariantViolation(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(webhooks(&conn).unwrap().is_empty());
+        assert!(queue_confirmed(&conn).unwrap() == 0);
     }
 
+    /// What the sync loop commits in a batch: a batch rolled back after its
+    /// blocks are applied and their payloads queued leaves neither behind.
     #[test]
-    fn test_blocks_to_apply() {
+    fn test_rolled_back_batch() {
         let mut conn = Connection::open(":memory:").unwrap();
+        let (genesis_prev, _) = prepare_fixture(&mut conn);
+
+        let epoch = |id: EpochId| decode_epoch(id, &fixture_pack(id)).unwrap();
+        import_epoch(&mut conn, &genesis_prev, 0, epoch(0)).unwrap();
+        import_epoch(&mut conn, &genesis_prev, 1, epoch(1)).unwrap();
+        let tip = last_applied_block(&conn).unwrap();
+
+        // a webhook notified of every transaction of the last epoch
+        let blocks = epoch(2);
+        let txs: Vec<TxAux> = blocks
+            .iter()
+            .flat_map(|(block, _)| block.get_transactions().into_iter().flatten())
+            .collect();
+        assert!(!txs.is_empty());
+        let txids: Vec<String> = txs.iter().map(|tx_aux| format!("{}", tx_aux.tx.id())).collect();
+        let addresses: Vec<String> = txs
+            .iter()
+            .flat_map(|tx_aux| tx_aux.tx.outputs.iter())
+            .map(|output| format!("{}", output.address))
+            .collect();
+        insert_webhook(&conn, "http://localhost/hook", "secret", 1, &addresses).unwrap();
+
+        let queued = |conn: &Connection| -> Vec<String> {
+            let mut stmt = conn
+                .prepare("SELECT payload FROM webhook_delivery ORDER BY id")
+                .unwrap();
+            let payloads = stmt
+                .query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<String>>>()
+                .unwrap();
+            payloads
+                .iter()
+                .map(|payload| serde_json::from_str::<WebhookPayload>(payload).unwrap().txid)
+                .collect()
+        };
+        let apply = |transaction: &Connection| {
+            for (block, raw) in &blocks {
+                apply_block(transaction, &genesis_prev, block, raw).unwrap();
+            }
+            queue_confirmed(transaction).unwrap()
+        };
+
+        {
+            let transaction = conn.transaction().unwrap();
+            assert!(apply(&transaction) == txids.len());
+        }
+        assert!(last_applied_block(&conn).unwrap() == tip);
+        assert!(queued(&conn).is_empty());
+
+        // applied again and committed, every payload is queued once, in
+        // chain order
+        let transaction = conn.transaction().unwrap();
+        assert!(apply(&transaction) == txids.len());
+        transaction.commit().unwrap();
+        assert!(queued(&conn) == txids);
+    }
 
+    #[test]
+    fn test_address_summary() {
+        let mut conn = Connection::open(":memory:").unwrap();
         prepare_schema(&conn).unwrap();
 
-        let next = None;
-        let initial = HeaderHash::from_str(
-            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
-        )
-        .unwrap();
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
+        let address_dest =
+            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
 
         let hash1 = HeaderHash::from_str(
             "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
         )
         .unwrap();
 
-        let hash2 = HeaderHash::from_str(
-            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91844",
-        )
-        .unwrap();
-
         conn.execute(
-            "insert or replace into block(id, next)
-                values (?1, ?2)
-                ",
-            params![
-                format!("{}", initial),
-                next.map(|h: HeaderHash| format!("{}", h))
-            ],
+            "insert into block(id, next, height, epoch, slot) values (?1, NULL, 1, 1, 2)",
+            params![format!("{}", hash1)],
         )
         .unwrap();
 
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address_dest.clone(),
+            value: Coin::new(6000).unwrap(),
+        });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(4000).unwrap(),
+        });
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+
+        let block1 = Some(BlockStatus {
+            hash: format!("{}", hash1),
+            epoch: Some(1),
+            slot: Some(2),
+        });
+
+        assert!(
+            address_summary(&conn, &address).unwrap()
+                == AddressSummary {
+                    address: addr_str.to_string(),
+                    first_seen: block1.clone(),
+                    last_seen: block1.clone(),
+                    tx_count: 2,
+                    total_received: 14000,
+                    total_sent: 10000,
+                    balance: 4000,
+                    utxo_count: 1,
+                }
+        );
+        assert!(balance_of(&conn, address.clone(), 0).unwrap().total == 4000);
+
+        assert!(
+            address_summary(&conn, &address_dest).unwrap()
+                == AddressSummary {
+                    address: addr_dest_str.to_string(),
+                    first_seen: block1.clone(),
+                    last_seen: block1.clone(),
+                    tx_count: 1,
+                    total_received: 6000,
+                    total_sent: 0,
+                    balance: 6000,
+                    utxo_count: 1,
+                }
+        );
+
+        revert_block(&conn, &hash1).unwrap();
+
+        assert!(
+            address_summary(&conn, &address).unwrap()
+                == AddressSummary {
+                    address: addr_str.to_string(),
+                    first_seen: None,
+                    last_seen: None,
+                    tx_count: 1,
+                    total_received: 10000,
+                    total_sent: 0,
+                    balance: 10000,
+                    utxo_count: 1,
+                }
+        );
+
+        let summary = address_summary(&conn, &address_dest).unwrap();
+        assert!(summary.tx_count == 0);
+        assert!(summary.balance == 0);
+        assert!(summary.utxo_count == 0);
+        assert!(summary.last_seen.is_none());
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let hashes: Vec<String> = (2..6)
+            .map(|i| {
+                format!(
+                    "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e9184{}",
+                    i
+                )
+            })
+            .collect();
+
+        // a boundary block, two main blocks, and a block only in the index
         conn.execute(
-            "insert or replace into last_block(id, block)
-                values (0, ?1)
-                ",
-            params![format!("{}", initial)],
+            "insert into block(id, next, previous, height, epoch, slot, issuer, tx_count, size)
+            values
+                (?1, ?2, NULL, 0, 1, NULL, NULL, 0, 100),
+                (?2, ?3, ?1, 1, 1, 2, 'issuer', 1, 200),
+                (?3, ?4, ?2, 2, 1, 3, 'issuer', 0, 150),
+                (?4, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL)",
+            params![hashes[0], hashes[1], hashes[2], hashes[3]],
         )
         .unwrap();
 
-        update_block_index(&mut conn, hash2.clone(), |h| {
-            if h == hash2 {
-                hash1.clone()
-            } else {
-                initial.clone()
-            }
-        })
-        .unwrap();
+        let hash1 = HeaderHash::from_str(&hashes[1]).unwrap();
 
-        let block1 = last_applied_block(&conn).unwrap().unwrap();
-        assert!(block1 == initial);
-        let block2 = next_block(&conn, block1).unwrap().unwrap();
-        assert!(block2 == hash1);
-        let block3 = next_block(&conn, block2).unwrap().unwrap();
-        assert!(block3 == hash2);
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+
+        assert!(
+            block_by_hash(&conn, &hash1).unwrap()
+                == BlockInfo {
+                    hash: hashes[1].clone(),
+                    previous: Some(hashes[0].clone()),
+                    next: Some(hashes[2].clone()),
+                    height: Some(1),
+                    epoch: 1,
+                    slot: Some(2),
+                    issuer: Some("issuer".to_string()),
+                    tx_count: Some(1),
+                    size: Some(200),
+                    transactions: vec![format!("{}", tx.id())],
+                }
+        );
+
+        // the next block is not applied yet
+        let last = block_by_hash(&conn, &HeaderHash::from_str(&hashes[2]).unwrap()).unwrap();
+        assert!(last.next.is_none());
+
+        match block_by_hash(&conn, &HeaderHash::from_str(&hashes[3]).unwrap()) {
+            Err(Error::NotFound(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert!(block_by_height(&conn, 1).unwrap().hash == hashes[1]);
+        assert!(block_by_height(&conn, 3).is_err());
+        assert!(block_by_date(&conn, 1, None).unwrap().hash == hashes[0]);
+        assert!(block_by_date(&conn, 1, Some(3)).unwrap().hash == hashes[2]);
+
+        let latest: Vec<String> = latest_blocks(&conn, 2)
+            .unwrap()
+            .into_iter()
+            .map(|block| block.hash)
+            .collect();
+        assert!(latest == vec![hashes[2].clone(), hashes[1].clone()]);
+
+        let epoch_page = |page: &Page| {
+            let (blocks, next) = epoch_blocks(&conn, 1, page).unwrap();
+            let hashes: Vec<String> = blocks.into_iter().map(|block| block.hash).collect();
+            (hashes, next)
+        };
+
+        let (epoch, next) = epoch_page(&Page::default());
+        assert!(epoch == hashes[..3].to_vec());
+        assert!(next.is_none());
+
+        // the transactions are read for the whole page at once
+        let (blocks, _) = epoch_blocks(&conn, 1, &Page::default()).unwrap();
+        let transactions: Vec<usize> = blocks
+            .iter()
+            .map(|block| block.transactions.len())
+            .collect();
+        assert!(transactions == vec![0, 1, 0]);
+
+        let mut page = Page {
+            limit: 2,
+            ..Page::default()
+        };
+        let (first, next) = epoch_page(&page);
+        assert!(first == hashes[..2].to_vec());
+
+        page.cursor = Some(format!("{}", next.unwrap()).parse().unwrap());
+        let (second, next) = epoch_page(&page);
+        assert!(second == vec![hashes[2].clone()]);
+        assert!(next.is_none());
+
+        let (newest, _) = epoch_page(&Page {
+            limit: 2,
+            cursor: None,
+            order: Order::Desc,
+        });
+        assert!(newest == vec![hashes[2].clone(), hashes[1].clone()]);
+
+        assert!(epoch_blocks(&conn, 2, &Page::default()).unwrap().0.is_empty());
     }
 
     #[test]
-    fn test_initial_state() {
+    fn test_submissions() {
         let mut conn = Connection::open(":memory:").unwrap();
         prepare_schema(&conn).unwrap();
 
         let mut utxos = BTreeMap::new();
 
         let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
-        let bytes = base58::decode(addr_str).unwrap();
-        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
         let id = hash::Blake2b256::new(&[0]);
 
-        let value = 10000;
-
         utxos.insert(
             TxoPointer { id, index: 0 },
             TxOut {
-                address,
-                value: Coin::new(value).unwrap(),
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
             },
         );
 
-        let initial = HeaderHash::from_str(
-            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
         )
         .unwrap();
-        apply_initial_state(&mut conn, &utxos).unwrap();
 
-        let address_rowid: i64 = conn
-            .query_row(
-                "SELECT rowid FROM address WHERE address=?1",
-                params![addr_str],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let input = TxoPointer { id, index: 0 };
+        assert!(output_spent(&conn, &input).unwrap() == Some(false));
+        assert!(output_spent(&conn, &TxoPointer { id, index: 1 }).unwrap().is_none());
 
-        assert!(address_rowid > 0);
+        let mut tx = Tx::new();
+        tx.add_input(input.clone());
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+        let txid = format!("{}", tx.id());
 
-        let tx_rowid: i64 = conn
-            .query_row(
-                "SELECT rowid FROM tx WHERE txid=?1",
-                params![format!("{}", id)],
-                |row| row.get(0),
-            )
+        match submission(&conn, &txid, 60) {
+            Err(Error::NotFound(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        record_pending(&conn, &txid).unwrap();
+        let pending = submission(&conn, &txid, 60).unwrap();
+        assert!(pending.status == SubmissionStatus::Pending);
+        assert!(pending.block.is_none());
+        assert!(pending.submitted_at.is_some());
+
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+        assert!(output_spent(&conn, &input).unwrap() == Some(true));
+        assert!(
+            submission(&conn, &txid, 60).unwrap()
+                == Submission {
+                    txid: txid.clone(),
+                    status: SubmissionStatus::Confirmed,
+                    submitted_at: pending.submitted_at,
+                    block: Some(format!("{}", hash1)),
+                }
+        );
+
+        revert_block(&conn, &hash1).unwrap();
+        assert!(submission(&conn, &txid, 60).unwrap().status == SubmissionStatus::Pending);
+
+        conn.execute(
+            "UPDATE pending_tx SET submitted_at = cast(strftime('%s', 'now') as integer) - 120",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+        assert!(submission(&conn, &txid, 60).unwrap().status == SubmissionStatus::Expired);
+
+        // still reported as expired during the retention
+        assert!(prune_pending(&conn, 60, 3600, 5).unwrap() == 0);
+        assert!(submission(&conn, &txid, 60).unwrap().status == SubmissionStatus::Expired);
+
+        // submitting it again restarts its timeout
+        record_pending(&conn, &txid).unwrap();
+        assert!(submission(&conn, &txid, 60).unwrap().status == SubmissionStatus::Pending);
+
+        // expired long enough ago, it is forgotten
+        conn.execute("UPDATE pending_tx SET submitted_at = 0", rusqlite::NO_PARAMS)
             .unwrap();
+        assert!(prune_pending(&conn, 60, 3600, 5).unwrap() == 1);
+        match submission(&conn, &txid, 60) {
+            Err(Error::NotFound(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
 
-        assert!(tx_rowid > 0);
+        // confirmed, it is kept until its block is stable
+        record_pending(&conn, &txid).unwrap();
+        insert_tx(&conn, &hash1, &date(), 0, tx.clone()).unwrap();
+        conn.execute(
+            "insert into block(id, next, height) values (?1, NULL, 10)",
+            params![format!("{}", hash1)],
+        )
+        .unwrap();
+        conn.execute(
+            "insert or replace into last_block(id, block) values (0, ?1)",
+            params![format!("{}", hash1)],
+        )
+        .unwrap();
+        assert!(prune_pending(&conn, 60, 3600, 0).unwrap() == 0);
+        assert!(submission(&conn, &txid, 60).unwrap().submitted_at.is_some());
 
-        let output_rowid: i64 = conn
-            .query_row(
-                "SELECT rowid FROM output WHERE 
-                            tx=?1 AND
-                            address=?2 AND
-                            offset=?3 AND
-                            value=?4
-                        ",
-                params![tx_rowid, address_rowid, 0, value as i64],
-                |row| row.get(0),
-            )
+        conn.execute("UPDATE block SET height = 4", rusqlite::NO_PARAMS)
+            .unwrap();
+        conn.execute(
+            "insert into block(id, next, height) values ('tip', NULL, 10)",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE last_block SET block = 'tip' WHERE id = 0",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+        assert!(prune_pending(&conn, 60, 3600, 5).unwrap() == 1);
+        assert!(
+            submission(&conn, &txid, 60).unwrap()
+                == Submission {
+                    txid: txid.clone(),
+                    status: SubmissionStatus::Confirmed,
+                    submitted_at: None,
+                    block: Some(format!("{}", hash1)),
+                }
+        );
+
+        // the transactions of the genesis were never submitted here
+        let genesis_txid: String = conn
+            .query_row("SELECT txid FROM tx", rusqlite::NO_PARAMS, |row| row.get(0))
             .unwrap();
+        let genesis = submission(&conn, &genesis_txid, 60).unwrap();
+        assert!(genesis.status == SubmissionStatus::Confirmed);
+        assert!(genesis.submitted_at.is_none());
+    }
+
+    #[test]
+    fn test_insert_tx_aux() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
+
+        let mut utxos = BTreeMap::new();
+
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
+
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
+
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let hash1 = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
 
-        assert!(output_rowid > 0);
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(10000).unwrap(),
+        });
+        let txid = format!("{}", tx.id());
+        let tx_aux = TxAux::new(tx.clone(), TxWitness::new());
+        let raw = to_cbor(&tx_aux).unwrap();
+
+        // the encoding of another transaction is refused
+        let mut other = tx.clone();
+        other.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(1).unwrap(),
+        });
+        let other_raw = to_cbor(&TxAux::new(other, TxWitness::new())).unwrap();
+        match insert_tx_aux(&conn, &hash1, &date(), 0, tx_aux.clone(), &other_raw) {
+            Err(Error::DecodeError(..)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match raw_transaction(&conn, &txid) {
+            Err(rusqlite::Error::QueryReturnedNoRows) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
 
-        let tx_by_address: i64 = conn
+        insert_tx_aux(&conn, &hash1, &date(), 0, tx_aux, &raw).unwrap();
+        assert!(raw_transaction(&conn, &txid).unwrap() == Some(raw));
+
+        // the genesis transactions have no encoding
+        let genesis_txid: String = conn
             .query_row(
-                "SELECT rowid FROM txs_by_address WHERE 
-                    tx=?1 AND
-                    address=?2
-                ",
-                params![tx_rowid, address_rowid],
+                "SELECT txid FROM tx WHERE txid != ?1",
+                params![txid],
                 |row| row.get(0),
             )
             .unwrap();
+        assert!(raw_transaction(&conn, &genesis_txid).unwrap().is_none());
+    }
 
-        assert!(tx_by_address > 0);
+    #[test]
+    fn test_tx_aux_encodings() {
+        let mut found = 0;
+        for epoch in 0..3 {
+            for (block, raw) in decode_epoch(epoch, &fixture_pack(epoch)).unwrap() {
+                let payload = match block.get_transactions() {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+                let txs: Vec<TxAux> = payload.into_iter().collect();
+                let encodings = tx_aux_encodings(&raw).unwrap();
+                assert!(encodings.len() == txs.len());
+
+                // every encoding decodes to the transaction at its index
+                for (tx_aux, encoding) in txs.iter().zip(&encodings) {
+                    let decoded: TxAux = Deserializer::from(std::io::Cursor::new(*encoding))
+                        .deserialize()
+                        .unwrap();
+                    assert!(decoded.tx.id() == tx_aux.tx.id());
+                }
+
+                // a block cut inside its last transaction is refused
+                if let Some(last) = encodings.last() {
+                    let end = last.as_ptr() as usize - raw.as_ptr() as usize + 1;
+                    assert!(tx_aux_encodings(&raw[..end]).is_err());
+                }
+                found += txs.len();
+            }
+        }
+        assert!(found == 6);
+
+        // hostile lengths and nesting are refused without overflowing
+        assert!(tx_aux_encodings(&[0x9f; 100_000]).is_err());
+        let huge = [0x82, 0x01, 0x83, 0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(tx_aux_encodings(&huge).is_err());
     }
 
     #[test]
-    fn test_add_tx() {
+    fn test_utxos() {
         let mut conn = Connection::open(":memory:").unwrap();
         prepare_schema(&conn).unwrap();
 
         let mut utxos = BTreeMap::new();
 
         let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
-        let bytes = base58::decode(addr_str).unwrap();
-        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
         let id = hash::Blake2b256::new(&[0]);
 
-        let value = 10000;
-
         utxos.insert(
             TxoPointer { id, index: 0 },
             TxOut {
                 address: address.clone(),
-                value: Coin::new(value).unwrap(),
+                value: Coin::new(10000).unwrap(),
             },
         );
 
-        let initial = HeaderHash::from_str(
-            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
-        )
-        .unwrap();
         apply_initial_state(&mut conn, &utxos).unwrap();
 
-        let mut tx = Tx::new();
+        assert!(
+            utxos_of(&conn, address.clone()).unwrap()
+                == vec![Utxo {
+                    txid: format!("{}", id),
+                    index: 0,
+                    value: 10000,
+                }]
+        );
 
-        let input = TxoPointer { id, index: 0 };
+        let block = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
 
         let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
         let address_dest =
             ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
 
-        let output = TxOut {
-            address: address_dest,
-            value: Coin::new(5000).unwrap(),
-        };
-
-        let rest = TxOut {
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address_dest.clone(),
+            value: Coin::new(4000).unwrap(),
+        });
+        tx.add_output(TxOut {
             address: address.clone(),
-            value: Coin::new(5000).unwrap(),
-        };
+            value: Coin::new(6000).unwrap(),
+        });
+
+        insert_tx(&conn, &block, &date(), 0, tx.clone()).unwrap();
+
+        assert!(
+            utxos_of(&conn, address.clone()).unwrap()
+                == vec![Utxo {
+                    txid: format!("{}", tx.id()),
+                    index: 1,
+                    value: 6000,
+                }]
+        );
 
-        tx.add_input(input);
-        tx.add_output(output);
-        tx.add_output(rest);
+        revert_block(&conn, &block).unwrap();
 
-        insert_tx(&mut conn, tx.clone()).unwrap();
+        assert!(utxos_of(&conn, address_dest.clone()).unwrap().is_empty());
+        assert!(utxos_of(&conn, address.clone()).unwrap().len() == 1);
+    }
 
-        let tx_rowid: i64 = conn
-            .query_row(
-                "SELECT rowid FROM tx WHERE txid=?1",
-                params![format!("{}", tx.id())],
-                |row| row.get(0),
-            )
-            .unwrap();
+    #[test]
+    fn test_balance() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        prepare_schema(&conn).unwrap();
 
-        assert!(tx_rowid == 2);
+        let mut utxos = BTreeMap::new();
 
-        let output1_rowid: i64 = conn
-            .query_row(
-                "SELECT output.id FROM output join address WHERE 
-                    tx=?1 AND
-                    address.address=?2 AND
-                    offset=?3 AND
-                    value=?4
-                ",
-                params![tx_rowid, addr_dest_str, 0, 5000i64],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
+        let id = hash::Blake2b256::new(&[0]);
 
-        assert!(output1_rowid > 0);
+        utxos.insert(
+            TxoPointer { id, index: 0 },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            },
+        );
 
-        let output2_rowid: i64 = conn
-            .query_row(
-                "SELECT output.id FROM output join address WHERE 
-                    tx=?1 AND
-                    address.address=?2 AND
-                    offset=?3 AND
-                    value=?4
-                ",
-                params![tx_rowid, addr_str, 1, 5000i64],
-                |row| row.get(0),
-            )
-            .unwrap();
+        utxos.insert(
+            TxoPointer {
+                id: hash::Blake2b256::new(&[1]),
+                index: 0,
+            },
+            TxOut {
+                address: address.clone(),
+                value: Coin::new(500).unwrap(),
+            },
+        );
 
-        assert!(output2_rowid > 0);
+        apply_initial_state(&mut conn, &utxos).unwrap();
 
-        let input_rowid: i64 = conn
-            .query_row(
-                "SELECT rowid FROM input WHERE 
-                    tx=?1 AND
-                    offset=?2
-                ",
-                params![tx_rowid, 0],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let block = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
 
-        assert!(input_rowid > 0);
+        conn.execute(
+            "insert into block(id, next, height) values (?1, NULL, 10)",
+            params![format!("{}", block)],
+        )
+        .unwrap();
 
-        let tx_by_address_1: i64 = conn
-            .query_row(
-                "SELECT txs_by_address.id FROM 
-                    txs_by_address join address
-                    on txs_by_address.address = address.id
-                WHERE 
-                    tx=?1 AND
-                    address.address=?2
-                ",
-                params![tx_rowid, addr_str],
-                |row| row.get(0),
-            )
-            .unwrap();
+        conn.execute(
+            "insert into last_block(id, block) values (0, ?1)",
+            params![format!("{}", block)],
+        )
+        .unwrap();
 
-        assert!(tx_by_address_1 > 0);
+        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
+        let address_dest =
+            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
 
-        let tx_by_address_2: i64 = conn
-            .query_row(
-                "SELECT txs_by_address.id FROM 
-                    txs_by_address join address
-                    on txs_by_address.address = address.id
-                WHERE 
-                    tx=?1 AND
-                    address.address=?2
-                ",
-                params![tx_rowid, addr_dest_str],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let mut tx = Tx::new();
+        tx.add_input(TxoPointer { id, index: 0 });
+        tx.add_output(TxOut {
+            address: address_dest.clone(),
+            value: Coin::new(4000).unwrap(),
+        });
+        tx.add_output(TxOut {
+            address: address.clone(),
+            value: Coin::new(6000).unwrap(),
+        });
+
+        insert_tx(&conn, &block, &date(), 0, tx.clone()).unwrap();
+
+        let balance = balance_of(&conn, address.clone(), 5).unwrap();
+        assert!(
+            balance
+                == Balance {
+                    total: 6500,
+                    stable: 500,
+                    unstable: 6000,
+                }
+        );
 
-        assert!(tx_by_address_2 > 0);
+        conn.execute(
+            "UPDATE block SET height = 4 WHERE id = ?1",
+            params![format!("{}", block)],
+        )
+        .unwrap();
+
+        conn.execute(
+            "insert into block(id, next, height) values ('tip', NULL, 10)",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+
+        conn.execute(
+            "UPDATE last_block SET block = 'tip' WHERE id = 0",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+
+        let balance = balance_of(&conn, address_dest.clone(), 5).unwrap();
+        assert!(
+            balance
+                == Balance {
+                    total: 4000,
+                    stable: 4000,
+                    unstable: 0,
+                }
+        );
+
+        // without the height of the block, its outputs may be recent
+        conn.execute(
+            "UPDATE block SET height = NULL WHERE id = ?1",
+            params![format!("{}", block)],
+        )
+        .unwrap();
+
+        let balance = balance_of(&conn, address.clone(), 5).unwrap();
+        assert!(
+            balance
+                == Balance {
+                    total: 6500,
+                    stable: 500,
+                    unstable: 6000,
+                }
+        );
+
+        // nor can it be told how deep they are without the height of the tip
+        conn.execute(
+            "UPDATE block SET height = CASE WHEN id = 'tip' THEN NULL ELSE 4 END",
+            rusqlite::NO_PARAMS,
+        )
+        .unwrap();
+
+        let balance = balance_of(&conn, address_dest.clone(), 5).unwrap();
+        assert!(
+            balance
+                == Balance {
+                    total: 4000,
+                    stable: 0,
+                    unstable: 4000,
+                }
+        );
     }
 
     #[test]
-    fn test_transactions_by_address() {
+    fn test_transactions_pages() {
         let mut conn = Connection::open(":memory:").unwrap();
         prepare_schema(&conn).unwrap();
 
         let mut utxos = BTreeMap::new();
 
         let addr_str = "Ae2tdPwUPEZKmwoy3AU3cXb5Chnasj6mvVNxV1H11997q3VW5ihbSfQwGpm";
-        let bytes = base58::decode(addr_str).unwrap();
-        let address = ExtendedAddr::try_from_slice(&bytes).unwrap();
+        let address = ExtendedAddr::try_from_slice(&base58::decode(addr_str).unwrap()).unwrap();
         let id = hash::Blake2b256::new(&[0]);
 
-        let value = 10000;
-
         utxos.insert(
             TxoPointer { id, index: 0 },
             TxOut {
                 address: address.clone(),
-                value: Coin::new(value).unwrap(),
+                value: Coin::new(10000).unwrap(),
             },
         );
 
-        let initial = HeaderHash::from_str(
+        apply_initial_state(&mut conn, &utxos).unwrap();
+
+        let block = HeaderHash::from_str(
+            "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91843",
+        )
+        .unwrap();
+        let earlier = HeaderHash::from_str(
             "ae443ffffe52cc29de83312d2819b3955fc306ce65ae6aa5b26f1d3c76e91842",
         )
         .unwrap();
-        apply_initial_state(&mut conn, &utxos).unwrap();
 
-        let mut tx = Tx::new();
+        conn.execute(
+            "insert into block(id, next, height) values (?1, ?2, 0), (?2, NULL, 1)",
+            params![format!("{}", earlier), format!("{}", block)],
+        )
+        .unwrap();
 
-        let input = TxoPointer { id, index: 0 };
+        let mut txids = vec![format!("{}", id)];
+        let mut source = TxoPointer { id, index: 0 };
 
-        let addr_dest_str = "DdzFFzCqrhsyhumccfGyEj3WZzztSPr92ntRWB6UVVwzcMTpwoafVQ5vD9mdZ5Xind8ycugbmA8esxmo7NycjQFGSbDeKrxabTz8MVzf";
-        let address_dest =
-            ExtendedAddr::try_from_slice(&base58::decode(addr_dest_str).unwrap()).unwrap();
+        for i in 0..4 {
+            let mut tx = Tx::new();
+            tx.add_input(source);
+            tx.add_output(TxOut {
+                address: address.clone(),
+                value: Coin::new(10000).unwrap(),
+            });
 
-        let output = TxOut {
-            address: address_dest.clone(),
-            value: Coin::new(5000).unwrap(),
-        };
+            insert_tx(&conn, &block, &date(), i, tx.clone()).unwrap();
 
-        let rest = TxOut {
+            txids.push(format!("{}", tx.id()));
+            source = TxoPointer {
+                id: tx.id(),
+                index: 0,
+            };
+        }
+
+        // indexed last, but in a block before the others
+        let mut tx = Tx::new();
+        tx.add_output(TxOut {
             address: address.clone(),
-            value: Coin::new(5000).unwrap(),
+            value: Coin::new(20000).unwrap(),
+        });
+        insert_tx(&conn, &earlier, &date(), 0, tx.clone()).unwrap();
+        txids.insert(1, format!("{}", tx.id()));
+
+        let mut page = Page {
+            limit: 2,
+            cursor: None,
+            order: Order::Asc,
         };
 
-        tx.add_input(input);
-        tx.add_output(output);
-        tx.add_output(rest);
+        let mut seen = vec![];
+        loop {
+            let (transactions, next) = transactions_of(&conn, &[address.clone()], &page).unwrap();
+            assert!(transactions.len() <= 2);
+            seen.extend(transactions.into_iter().map(|tx| tx.txid));
 
-        insert_tx(&mut conn, tx.clone()).unwrap();
+            // clients hand the cursor back as a string
+            match next {
+                Some(cursor) => page.cursor = Some(cursor.to_string().parse().unwrap()),
+                None => break,
+            }
+        }
 
-        let transactions = transactions_of(&conn, address.clone()).unwrap();
+        assert!(seen == txids);
 
-        let transaction1 = Transaction {
-            txid: format!("{}", id),
-            inputs: vec![],
-            outputs: vec![Output {
-                value: 10000,
-                address: format!("{}", address.clone()),
-            }],
+        let page = Page {
+            limit: 3,
+            cursor: None,
+            order: Order::Desc,
         };
 
-        assert!(transactions
-            .iter()
-            .any(|transaction| { transaction == &transaction1 }));
+        let (transactions, next) = transactions_of(&conn, &[address.clone()], &page).unwrap();
+        let newest: Vec<String> = transactions.into_iter().map(|tx| tx.txid).collect();
+        assert!(newest == txids.iter().rev().take(3).cloned().collect::<Vec<_>>());
 
-        let transaction2 = Transaction {
-            txid: format!("{}", tx.id()),
-            inputs: vec![Input {
-                id: format!("{}", id),
-                index: 0,
-            }],
-            outputs: vec![
-                Output {
-                    address: format!("{}", address_dest.clone()),
-                    value: 5000,
-                },
-                Output {
-                    address: format!("{}", address.clone()),
-                    value: 5000,
-                },
-            ],
+        let page = Page {
+            cursor: next,
+            ..page
         };
 
-        assert!(transactions
-            .iter()
-            .any(|transaction| { transaction == &transaction2 }));
+        let (transactions, next) = transactions_of(&conn, &[address.clone()], &page).unwrap();
+        assert!(transactions.len() == 3);
+        assert!(transactions[2].txid == txids[0]);
+        assert!(next.is_none());
+
+        assert!("not a cursor".parse::<Cursor>().is_err());
+        assert!(Page::default().limit == DEFAULT_PAGE_LIMIT);
     }
 }
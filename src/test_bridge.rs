@@ -0,0 +1,190 @@
+//! A stand-in for the http-bridge, serving a chain stored on disk so the
+//! whole sync path can be exercised without a node.
+
+use cardano::block::block::Block;
+use cardano::block::types::HeaderHash;
+use cardano::block::RawBlock;
+use cbor_event::se::Serializer;
+use iron::status::Status;
+use iron::{status, Handler, Iron, IronResult, Listening, Request, Response};
+use router::Router;
+use storage_units::packfile::Reader;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// What the stand-in answers with, all in the bridge wire format.
+struct Served {
+    tip: Vec<u8>,
+    blocks: HashMap<String, Vec<u8>>,
+    epochs: Vec<Vec<u8>>,
+}
+
+/// Serves `/tip`, `/block/:hash` and `/epoch/:id` for the epoch packs found
+/// in `<fixture>/epoch/<id>`, as written by `tests/fixtures/generate.py`, and
+/// accepts any transaction POSTed to `/txs/signed`.
+///
+/// Requests are answered with the statuses pushed to `failures` first, one
+/// each, to stand for a failing bridge.
+///
+/// The tip is the block at `tip_offset` in the last recorded epoch, so all
+/// the epochs before it are served packed and the blocks of the last one
+/// have to be fetched one by one, as with a live bridge.
+pub struct TestBridge {
+    pub url: String,
+    /// the blocks up to the tip, in chain order
+    pub blocks: Vec<Block>,
+    /// the cbor of the transactions submitted so far
+    pub submitted: Arc<Mutex<Vec<Vec<u8>>>>,
+    pub failures: Arc<Mutex<VecDeque<Status>>>,
+    /// the requests received so far, failed ones included
+    pub requests: Arc<AtomicUsize>,
+    _listening: Listening,
+}
+
+fn read_pack(pack: &[u8]) -> Vec<(Vec<u8>, Block)> {
+    let mut reader = Reader::init(pack).expect("epoch pack");
+    let mut blocks = vec![];
+
+    while let Some(raw) = reader.next_block().expect("packed block") {
+        let block = RawBlock(raw.clone()).decode().expect("valid block");
+        blocks.push((raw, block));
+    }
+
+    blocks
+}
+
+impl TestBridge {
+    pub fn start(fixture: &Path, tip_offset: usize) -> TestBridge {
+        let mut epochs = vec![];
+        while let Ok(pack) = fs::read(fixture.join("epoch").join(epochs.len().to_string())) {
+            epochs.push(pack);
+        }
+
+        let last_epoch = epochs.pop().unwrap_or_else(|| {
+            panic!(
+                "no epoch in {}, see tests/fixtures/generate.py",
+                fixture.display()
+            )
+        });
+
+        let mut raw_blocks = HashMap::new();
+        let mut blocks = vec![];
+        let packed = epochs.iter().flat_map(|pack| read_pack(pack));
+        let unpacked = read_pack(&last_epoch).into_iter().take(tip_offset + 1);
+
+        for (raw, block) in packed.chain(unpacked) {
+            raw_blocks.insert(format!("{}", block.header().compute_hash()), raw);
+            blocks.push(block);
+        }
+
+        let mut tip = Serializer::new_vec();
+        tip.serialize(&blocks.last().expect("tip block").header())
+            .expect("tip header");
+
+        let served = Arc::new(Served {
+            tip: tip.finalize(),
+            blocks: raw_blocks,
+            epochs,
+        });
+
+        let mut router = Router::new();
+
+        let tip = served.clone();
+        router.get(
+            "/:network/tip",
+            move |_: &mut Request| -> IronResult<Response> {
+                Ok(Response::with((status::Ok, tip.tip.clone())))
+            },
+            "tip",
+        );
+
+        let blocks = served.clone();
+        router.get(
+            "/:network/block/:hash",
+            move |req: &mut Request| -> IronResult<Response> {
+                let hash = req.extensions.get::<Router>().unwrap().find("hash").unwrap();
+
+                match blocks.blocks.get(hash) {
+                    Some(raw) => Ok(Response::with((status::Ok, raw.clone()))),
+                    None => Ok(Response::with(status::NotFound)),
+                }
+            },
+            "block",
+        );
+
+        let epochs = served.clone();
+        router.get(
+            "/:network/epoch/:id",
+            move |req: &mut Request| -> IronResult<Response> {
+                let id = req.extensions.get::<Router>().unwrap().find("id").unwrap();
+
+                match id.parse().ok().and_then(|id: usize| epochs.epochs.get(id)) {
+                    Some(pack) => Ok(Response::with((status::Ok, pack.clone()))),
+                    None => Ok(Response::with(status::NotFound)),
+                }
+            },
+            "epoch",
+        );
+
+        let submitted = Arc::new(Mutex::new(vec![]));
+        let transactions = submitted.clone();
+        router.post(
+            "/:network/txs/signed",
+            move |req: &mut Request| -> IronResult<Response> {
+                let mut body = vec![];
+                let signed = req
+                    .body
+                    .read_to_end(&mut body)
+                    .ok()
+                    .and_then(|_| serde_json::from_slice::<serde_json::Value>(&body).ok())
+                    .and_then(|body| body["signedTx"].as_str().map(base64::decode));
+
+                match signed {
+                    Some(Ok(raw)) => {
+                        transactions.lock().unwrap().push(raw);
+                        Ok(Response::with(status::Ok))
+                    }
+                    _ => Ok(Response::with(status::BadRequest)),
+                }
+            },
+            "transaction",
+        );
+
+        let failures = Arc::new(Mutex::new(VecDeque::new()));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let failing = failures.clone();
+        let received = requests.clone();
+        let handler = move |req: &mut Request| -> IronResult<Response> {
+            received.fetch_add(1, Ordering::SeqCst);
+
+            let failure = failing.lock().unwrap().pop_front();
+            match failure {
+                Some(status) => Ok(Response::with(status)),
+                None => router.handle(req),
+            }
+        };
+
+        let listening = Iron::new(handler)
+            .http("127.0.0.1:0")
+            .expect("start test bridge");
+
+        TestBridge {
+            url: format!("http://{}/mainnet/", listening.socket),
+            blocks,
+            submitted,
+            failures,
+            requests,
+            _listening: listening,
+        }
+    }
+
+    pub fn tip(&self) -> HeaderHash {
+        self.blocks.last().unwrap().header().compute_hash()
+    }
+}
@@ -1,17 +1,40 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Transaction {
     pub txid: String,
+    // the location fields are unknown for genesis transactions and for
+    // transactions imported by older versions
+    pub block: Option<String>,
+    pub epoch: Option<i64>,
+    pub slot: Option<i32>,
+    pub index_in_block: Option<i32>,
     pub inputs: Vec<Input>,
-    pub outputs: Vec<Output>
+    pub outputs: Vec<Output>,
+    pub total_input: i64,
+    pub total_output: i64,
+    pub fee: Option<i64>,
+}
+
+/// A transaction of a batch history query, with the requested addresses it
+/// involves.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct MatchedTransaction {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    pub addresses: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Input {
     pub id: String,
-    pub index: i32
+    pub index: i32,
+    // the output consumed by the input
+    pub address: String,
+    pub value: i64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -21,11 +44,284 @@ pub struct Output {
     pub value: i64,
 }
 
+/// Decoded view of the cbor of a transaction with its witnesses.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RawTransaction {
+    pub txid: String,
+    pub cbor: String,
+    pub witnesses: Vec<Witness>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "type")]
+pub enum Witness {
+    PkWitness { public_key: String },
+    RedeemWitness { public_key: String },
+    ScriptWitness,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Utxo {
+    pub txid: String,
+    pub index: i32,
+    pub value: i64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Balance {
+    pub total: i64,
+    pub stable: i64,
+    pub unstable: i64,
+}
+
+/// Body of the error responses. `code` is stable for clients to match on,
+/// `details` holds the values the error is about.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+/// What the sync thread last did, shared with the server.
+#[derive(Default)]
+pub struct SyncState {
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<(SystemTime, String)>,
+    // the bridge tip as last read, and its height once the block index
+    // reaches it
+    pub tip: Option<BlockStatus>,
+    pub tip_height: Option<i64>,
+    // why the tip could not be read the last time, cleared once it is
+    pub bridge_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BlockStatus {
+    pub hash: String,
+    // unknown for blocks imported by older versions, slot is null for
+    // boundary blocks
+    pub epoch: Option<i64>,
+    pub slot: Option<i64>,
+}
+
+/// An applied block, with its header fields and the ids of its transactions
+/// in the block order.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BlockInfo {
+    pub hash: String,
+    pub previous: Option<String>,
+    // null for the last applied block
+    pub next: Option<String>,
+    pub height: Option<i64>,
+    pub epoch: i64,
+    // null for boundary blocks, as the issuer
+    pub slot: Option<i64>,
+    pub issuer: Option<String>,
+    pub tx_count: Option<i64>,
+    pub size: Option<i64>,
+    pub transactions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ErrorStatus {
+    pub time: u64,
+    pub message: String,
+}
+
+/// Progress of the sync, times are in seconds since the unix epoch.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Status {
+    // null until the sync loop reads it from the bridge, see bridge_error
+    pub tip: Option<BlockStatus>,
+    pub last_applied: Option<BlockStatus>,
+    pub blocks_behind: Option<u64>,
+    pub slots_behind: Option<u64>,
+    pub last_sync: Option<u64>,
+    pub last_error: Option<ErrorStatus>,
+    pub bridge_error: Option<String>,
+}
+
+/// Overview of the history of an address. The first and last blocks are null
+/// until the address is involved in a transaction outside the genesis.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AddressSummary {
+    pub address: String,
+    pub first_seen: Option<BlockStatus>,
+    pub last_seen: Option<BlockStatus>,
+    pub tx_count: i64,
+    pub total_received: i64,
+    pub total_sent: i64,
+    pub balance: i64,
+    pub utxo_count: i64,
+}
+
+/// A transaction of an applied block, with the addresses whose history it
+/// joins.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Activity {
+    pub txid: String,
+    pub addresses: Vec<String>,
+}
+
+/// The transactions brought by an applied block, in the block order.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BlockActivity {
+    pub block: String,
+    // unknown for blocks imported by older versions, slot is null for
+    // boundary blocks
+    pub epoch: Option<i64>,
+    pub slot: Option<i64>,
+    pub transactions: Vec<Activity>,
+}
+
+/// Blocks reverted by a fork, newest first, back to `ancestor`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Rollback {
+    pub ancestor: String,
+    pub reverted: Vec<String>,
+}
+
+/// A webhook registered through the admin api. The secret signing its
+/// payloads is never sent back.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub confirmations: u32,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    // the transaction is `confirmations` blocks deep
+    Confirmed,
+    // a rollback reverted a transaction notified as confirmed
+    Reverted,
+}
+
+/// Body POSTed to a webhook about one transaction.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub webhook: i64,
+    pub txid: String,
+    pub block: String,
+    pub height: Option<i64>,
+    // the addresses of the webhook involved in the transaction
+    pub addresses: Vec<String>,
+}
+
+/// A payload waiting in the queue to be delivered.
+#[derive(PartialEq, Debug)]
+pub struct Delivery {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    pub payload: String,
+    pub attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    // sent to the bridge, not seen in an applied block yet
+    Pending,
+    // in an applied block
+    Confirmed,
+    // still not in a block after the pending timeout
+    Expired,
+}
+
+/// A transaction sent through `POST /transaction`, or found in the index.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Submission {
+    pub txid: String,
+    pub status: SubmissionStatus,
+    // unix time of the submission, null for transactions not submitted here
+    pub submitted_at: Option<i64>,
+    pub block: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// The page size when the request doesn't set one, and the largest allowed.
+pub const DEFAULT_PAGE_LIMIT: u32 = 100;
+pub const MAX_PAGE_LIMIT: u32 = 1000;
+
+/// Position of a transaction in the chain: the height of its block and its
+/// index in it. Genesis transactions come first, at height -1, in the order
+/// they were indexed. Clients get it as an opaque string and hand it back to
+/// get the next page.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cursor {
+    pub height: i64,
+    pub index: i64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let position = format!("{}:{}", self.height, self.index);
+        write!(
+            f,
+            "{}",
+            base64::encode_config(&position, base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid cursor {}", s);
+        let position = base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let position = String::from_utf8(position).map_err(|_| invalid())?;
+
+        let mut parts = position.splitn(2, ':');
+        match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+            (Some(Ok(height)), Some(Ok(index))) => Ok(Cursor { height, index }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Page {
+    pub limit: u32,
+    pub cursor: Option<Cursor>,
+    pub order: Order,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            limit: DEFAULT_PAGE_LIMIT,
+            cursor: None,
+            order: Order::Asc,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Debug)]
 pub enum Error {
     DatabaseError(rusqlite::Error),
-    ConnectionError(reqwest::Error),
+    BridgeError(crate::http_bridge::Error),
+    PoolError(r2d2::Error),
+    // what could not be decoded, and why
+    DecodeError(String, String),
+    // the data contradicts what the importer relies on, such as a transaction
+    // spending an output that isn't indexed
+    InvariantViolation(String),
+    // what was looked up
+    NotFound(String),
 }
 
 impl From<rusqlite::Error> for Error {
@@ -34,9 +330,15 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
-impl From<reqwest::Error> for Error {
-    fn from(error: reqwest::Error) -> Self {
-        Error::ConnectionError(error)
+impl From<crate::http_bridge::Error> for Error {
+    fn from(error: crate::http_bridge::Error) -> Self {
+        Error::BridgeError(error)
+    }
+}
+
+impl From<r2d2::Error> for Error {
+    fn from(error: r2d2::Error) -> Self {
+        Error::PoolError(error)
     }
 }
 
@@ -44,7 +346,15 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::DatabaseError(ref err) => fmt::Display::fmt(err, f),
-            Error::ConnectionError(ref err) => fmt::Display::fmt(err, f),
+            Error::BridgeError(ref err) => fmt::Display::fmt(err, f),
+            Error::PoolError(ref err) => write!(f, "No database connection available: {}", err),
+            Error::DecodeError(ref what, ref reason) => {
+                write!(f, "Could not decode {}: {}", what, reason)
+            }
+            Error::InvariantViolation(ref message) => write!(f, "Inconsistent data: {}", message),
+            Error::NotFound(ref what) => write!(f, "{} not found", what),
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::error::Error for Error {}
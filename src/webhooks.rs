@@ -0,0 +1,104 @@
+//! Delivery of the webhook payloads queued by the sync loop.
+
+use cryptoxide::hmac::Hmac;
+use cryptoxide::mac::Mac;
+use cryptoxide::sha2::Sha256;
+
+use crate::metrics;
+use crate::storage;
+use crate::types::{Delivery, Error};
+use crate::Pool;
+
+use cardano::util::hex;
+use std::thread;
+use std::time::Duration;
+
+/// Header with the hex HMAC-SHA256 of the body, keyed by the webhook secret.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// Header with the id of the delivery, the same for all its attempts.
+pub const DELIVERY_HEADER: &str = "X-Delivery-Id";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DELIVERIES_PER_POLL: u32 = 100;
+// seconds before the first retry, doubled for each of the next ones up to
+// MAX_RETRY_DELAY
+const RETRY_DELAY: u64 = 10;
+const MAX_RETRY_DELAY: u64 = 3600;
+
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut hmac = Hmac::new(Sha256::new(), secret.as_bytes());
+    hmac.input(payload.as_bytes());
+    hex::encode(hmac.result().code())
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    RETRY_DELAY
+        .saturating_mul(2u64.saturating_pow(attempts))
+        .min(MAX_RETRY_DELAY)
+}
+
+fn post(client: &reqwest::Client, delivery: &Delivery) -> Result<(), String> {
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(
+            SIGNATURE_HEADER,
+            format!("sha256={}", sign(&delivery.secret, &delivery.payload)),
+        )
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .body(delivery.payload.clone())
+        .send()
+        .map_err(|e| format!("{}", e))?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("{} answered {}", delivery.url, response.status()))
+    }
+}
+
+fn deliver_due(pool: &Pool, client: &reqwest::Client) -> Result<(), Error> {
+    // no connection is held while posting, the webhooks can take their timeout
+    let deliveries = storage::due_deliveries(&pool.get()?, DELIVERIES_PER_POLL)?;
+
+    for delivery in deliveries {
+        let result = post(client, &delivery);
+        let conn = pool.get()?;
+
+        match result {
+            Ok(()) => {
+                storage::delivery_succeeded(&conn, delivery.id)?;
+                metrics::inc(metrics::WEBHOOK_DELIVERIES, &[("result", "delivered")], 1.0);
+            }
+            Err(e) => {
+                let delay = retry_delay(delivery.attempts);
+                warn!(
+                    "Delivery {} to {} failed, retrying in {}s: {}",
+                    delivery.id, delivery.url, delay, e
+                );
+                storage::delivery_failed(&conn, delivery.id, delay, &e)?;
+                metrics::inc(metrics::WEBHOOK_DELIVERIES, &[("result", "failed")], 1.0);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deliver the queued payloads forever. They stay queued until their webhook
+/// accepts them with a 2xx status or is deleted.
+pub fn run(pool: Pool) {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .expect("http client");
+
+    loop {
+        if let Err(e) = deliver_due(&pool, &client) {
+            error!("Could not deliver the webhook payloads: {}", e);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,446 @@
+#!/usr/bin/env python3
+"""Generate the synthetic chain served by the test bridge of the sync test in
+src/main.rs:
+
+    tests/fixtures/generate.py
+
+It writes, in tests/fixtures/synthetic:
+ - genesis.json: a network with k = 2 funding two addresses
+ - epoch/<id>: the epoch packs, in the format of the http-bridge storage
+ - expected/*.json: the responses the importer has to give for the chain
+
+The blocks and transactions are encoded as on the Byron mainnet, but their
+signatures and body proofs are placeholders: only what the importer reads is
+consistent. Blocks are linked by the hash of their header, and transactions
+spend the genesis outputs or each other's.
+"""
+
+import hashlib
+import json
+import os
+import shutil
+import struct
+import zlib
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+FIXTURE = os.path.join(HERE, "synthetic")
+
+PROTOCOL_MAGIC = 1
+K = 2
+# main blocks per epoch, after the boundary block
+SLOTS = 6
+FEE = 1000
+
+
+def blake2b256(data):
+    return hashlib.blake2b(data, digest_size=32).digest()
+
+
+def blake2b224(data):
+    return hashlib.blake2b(data, digest_size=28).digest()
+
+
+# cbor, with the definite lengths and minimal integers of the canonical
+# encoding, and indefinite arrays where the Byron encoding has them
+
+
+class Indefinite(list):
+    pass
+
+
+class Tag(object):
+    def __init__(self, tag, value):
+        self.tag = tag
+        self.value = value
+
+
+class Raw(object):
+    """Bytes already encoded, inserted as they are."""
+
+    def __init__(self, data):
+        self.data = data
+
+
+def head(major, n):
+    if n < 24:
+        return bytes([major << 5 | n])
+    if n < 0x100:
+        return bytes([major << 5 | 24, n])
+    if n < 0x10000:
+        return bytes([major << 5 | 25]) + struct.pack(">H", n)
+    if n < 0x100000000:
+        return bytes([major << 5 | 26]) + struct.pack(">I", n)
+    return bytes([major << 5 | 27]) + struct.pack(">Q", n)
+
+
+def cbor(value):
+    if isinstance(value, Raw):
+        return value.data
+    if isinstance(value, bool) or value is None:
+        raise TypeError(value)
+    if isinstance(value, int):
+        return head(0, value)
+    if isinstance(value, bytes):
+        return head(2, len(value)) + value
+    if isinstance(value, str):
+        data = value.encode()
+        return head(3, len(data)) + data
+    if isinstance(value, Indefinite):
+        return b"\x9f" + b"".join(cbor(v) for v in value) + b"\xff"
+    if isinstance(value, list):
+        return head(4, len(value)) + b"".join(cbor(v) for v in value)
+    if isinstance(value, dict):
+        return head(5, len(value)) + b"".join(cbor(k) + cbor(v) for k, v in value.items())
+    if isinstance(value, Tag):
+        return head(6, value.tag) + cbor(value.value)
+    raise TypeError(value)
+
+
+def byron_list(items):
+    """Lists are indefinite unless empty, as the Haskell nodes encode them."""
+    return Indefinite(items) if items else []
+
+
+# addresses
+
+ALPHABET = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz"
+
+
+def base58(data):
+    n = int.from_bytes(data, "big")
+    encoded = ""
+    while n > 0:
+        n, rest = divmod(n, 58)
+        encoded = ALPHABET[rest] + encoded
+    zeros = len(data) - len(data.lstrip(b"\0"))
+    return "1" * zeros + encoded
+
+
+class Address(object):
+    def __init__(self, seed):
+        # a public key address without attributes, as in the bootstrap era
+        inner = cbor([blake2b224(seed.encode()), {}, 0])
+        self.cbor = cbor([Tag(24, inner), zlib.crc32(inner) & 0xFFFFFFFF])
+        self.base58 = base58(self.cbor)
+
+    def __str__(self):
+        return self.base58
+
+
+def placeholder(what, size):
+    data = b""
+    while len(data) < size:
+        data += blake2b256((what + str(len(data))).encode())
+    return data[:size]
+
+
+# transactions
+
+
+class Tx(object):
+    def __init__(self, name, inputs, outputs):
+        # inputs are (txid, index, address, value) of the spent outputs
+        self.name = name
+        self.inputs = inputs
+        self.outputs = outputs
+        self.tx = [
+            Indefinite(
+                [[0, Tag(24, cbor([txid, index]))] for (txid, index, _, _) in inputs]
+            ),
+            Indefinite([[Raw(address.cbor), value] for (address, value) in outputs]),
+            {},
+        ]
+        self.id = blake2b256(cbor(self.tx))
+        self.witnesses = [
+            [
+                0,
+                Tag(
+                    24,
+                    cbor(
+                        [
+                            placeholder("key %s %d" % (name, i), 64),
+                            placeholder("signature %s %d" % (name, i), 64),
+                        ]
+                    ),
+                ),
+            ]
+            for i in range(len(inputs))
+        ]
+
+    def output(self, index):
+        address, value = self.outputs[index]
+        return (self.id, index, address, value)
+
+
+def genesis_output(address, value):
+    # the genesis funds an address with an output of a transaction whose id
+    # is the hash of the address
+    return (blake2b256(address.cbor), 0, address, value)
+
+
+# blocks
+
+
+def header_hash(tag, header):
+    return blake2b256(cbor([tag, header]))
+
+
+def boundary_block(previous, epoch, difficulty):
+    body = []
+    header = [
+        PROTOCOL_MAGIC,
+        previous,
+        blake2b256(cbor(body)),
+        [epoch, [difficulty]],
+        [{}],
+    ]
+    return header_hash(0, header), cbor([0, [header, body, [{}]]])
+
+
+def main_block(previous, epoch, slot, difficulty, txs):
+    tx_payload = byron_list([[tx.tx, tx.witnesses] for tx in txs])
+    ssc = [3, Tag(258, [])]
+    delegation = []
+    update = [[], []]
+    body = [tx_payload, ssc, delegation, update]
+
+    proof = [
+        [
+            len(txs),
+            blake2b256(cbor(byron_list([tx.tx for tx in txs]))),
+            blake2b256(cbor(byron_list([tx.witnesses for tx in txs]))),
+        ],
+        [3, blake2b256(cbor(ssc))],
+        blake2b256(cbor(delegation)),
+        blake2b256(cbor(update)),
+    ]
+    consensus = [
+        [epoch, slot],
+        placeholder("leader", 64),
+        [difficulty],
+        [0, placeholder("block %d %d" % (epoch, slot), 64)],
+    ]
+    extra = [[0, 1, 0], ["cardano-sl", 1], {}, blake2b256(cbor([{}]))]
+    header = [PROTOCOL_MAGIC, previous, proof, consensus, extra]
+
+    return header_hash(1, header), cbor([1, [header, body, [{}]]])
+
+
+def pack(blocks):
+    data = b"\x7fCARDANO" + struct.pack(">II", 0x5041434B, 1)
+    for block in blocks:
+        data += struct.pack(">I", len(block)) + block
+        data += b"\0" * (-len(block) % 4)
+    return data
+
+
+# the chain
+
+addr1 = Address("address 1")
+addr2 = Address("address 2")
+addr3 = Address("address 3")
+addr4 = Address("address 4")
+
+g1 = genesis_output(addr1, 1000000)
+g3 = genesis_output(addr3, 2000000)
+
+
+def spend(name, inputs, outputs):
+    total = sum(value for (_, _, _, value) in inputs)
+    assert total - sum(value for (_, value) in outputs) == FEE, name
+    return Tx(name, inputs, outputs)
+
+
+tx_a = spend("a", [g1], [(addr2, 600000), (addr1, 399000)])
+tx_b = spend("b", [tx_a.output(0)], [(addr3, 500000), (addr2, 99000)])
+tx_c = spend("c", [g3, tx_b.output(0)], [(addr4, 2499000)])
+tx_d = spend("d", [tx_b.output(1), tx_a.output(1)], [(addr1, 497000)])
+tx_e = spend("e", [tx_d.output(0)], [(addr2, 300000), (addr4, 196000)])
+# spends an output of the same block
+tx_f = spend("f", [tx_e.output(0)], [(addr3, 299000)])
+
+# transactions by (epoch, slot)
+TXS = {
+    (0, 1): [tx_a],
+    (1, 2): [tx_b],
+    (2, 1): [tx_c],
+    (2, 2): [tx_d],
+    (2, 3): [tx_e, tx_f],
+}
+# the sync test serves the last epoch up to slot K + 1, so all the
+# transactions are applied, the ones of epoch 2 by the sync loop
+
+
+def genesis_json():
+    return {
+        "avvmDistr": {},
+        "blockVersionData": {
+            "heavyDelThd": "300000000000",
+            "maxBlockSize": "2000000",
+            "maxHeaderSize": "2000000",
+            "maxProposalSize": "700",
+            "maxTxSize": "4096",
+            "mpcThd": "20000000000000",
+            "scriptVersion": 0,
+            "slotDuration": "20000",
+            "softforkRule": {
+                "initThd": "900000000000000",
+                "minThd": "600000000000000",
+                "thdDecrement": "50000000000000",
+            },
+            "txFeePolicy": {
+                "multiplier": "43946000000",
+                "summand": "155381000000000",
+            },
+            "unlockStakeEpoch": "18446744073709551615",
+            "updateImplicit": "10000",
+            "updateProposalThd": "100000000000000",
+            "updateVoteThd": "1000000000000",
+        },
+        "bootStakeholders": {},
+        "ftsSeed": "76617361206f7061736120736b6f766f726f64612047677572646120626f726f64612070726f766f6461",
+        "heavyDelegation": {},
+        "nonAvvmBalances": {
+            str(address): str(value) for (_, _, address, value) in [g1, g3]
+        },
+        "protocolConsts": {
+            "k": K,
+            "protocolMagic": PROTOCOL_MAGIC,
+            "vssMaxTTL": 6,
+            "vssMinTTL": 2,
+        },
+        "startTime": 1506203091,
+        "vssCerts": {},
+    }
+
+
+def build():
+    genesis = genesis_json()
+    # the hash of the canonical json, as for the predefined networks
+    previous = blake2b256(json.dumps(genesis, sort_keys=True, separators=(",", ":")).encode())
+
+    epochs = []
+    located = {}
+    difficulty = 0
+    for epoch in range(3):
+        hash, block = boundary_block(previous, epoch, difficulty)
+        blocks = [block]
+        previous = hash
+
+        for slot in range(SLOTS):
+            difficulty += 1
+            txs = TXS.get((epoch, slot), [])
+            hash, block = main_block(previous, epoch, slot, difficulty, txs)
+            blocks.append(block)
+            previous = hash
+
+            for index, tx in enumerate(txs):
+                located[tx.id] = (hash, epoch, slot, index)
+
+        epochs.append(blocks)
+
+    return genesis, epochs, located
+
+
+def transaction_json(tx, located):
+    block, epoch, slot, index = located[tx.id]
+    inputs = [
+        {"id": txid.hex(), "index": i, "address": str(address), "value": value}
+        for (txid, i, address, value) in tx.inputs
+    ]
+    outputs = [{"address": str(address), "value": value} for (address, value) in tx.outputs]
+    total_input = sum(input["value"] for input in inputs)
+    total_output = sum(output["value"] for output in outputs)
+
+    return {
+        "txid": tx.id.hex(),
+        "block": block.hex(),
+        "epoch": epoch,
+        "slot": slot,
+        "index_in_block": index,
+        "inputs": inputs,
+        "outputs": outputs,
+        "total_input": total_input,
+        "total_output": total_output,
+        "fee": total_input - total_output,
+    }
+
+
+def raw_transaction_json(tx):
+    keys = [placeholder("key %s %d" % (tx.name, i), 64) for i in range(len(tx.inputs))]
+    return {
+        "txid": tx.id.hex(),
+        "cbor": cbor([tx.tx, tx.witnesses]).hex(),
+        "witnesses": [{"type": "PkWitness", "public_key": key.hex()} for key in keys],
+    }
+
+
+def genesis_transaction_json(output):
+    txid, _, address, value = output
+    return {
+        "txid": txid.hex(),
+        "block": None,
+        "epoch": None,
+        "slot": None,
+        "index_in_block": None,
+        "inputs": [],
+        "outputs": [{"address": str(address), "value": value}],
+        "total_input": 0,
+        "total_output": value,
+        "fee": None,
+    }
+
+
+def history_json(address, located):
+    history = [
+        genesis_transaction_json(output) for output in [g1, g3] if output[2] is address
+    ]
+    for (_, txs) in sorted(TXS.items()):
+        for tx in txs:
+            spent = [a for (_, _, a, _) in tx.inputs]
+            received = [a for (a, _) in tx.outputs]
+            if address in spent or address in received:
+                history.append(transaction_json(tx, located))
+    return history
+
+
+def write(path, data, mode="wb"):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, mode) as f:
+        f.write(data)
+
+
+def main():
+    genesis, epochs, located = build()
+
+    shutil.rmtree(FIXTURE, ignore_errors=True)
+    write(os.path.join(FIXTURE, "genesis.json"), json.dumps(genesis, indent=4) + "\n", "w")
+
+    for epoch, blocks in enumerate(epochs):
+        write(os.path.join(FIXTURE, "epoch", str(epoch)), pack(blocks))
+
+    expected = {
+        "history_address_2": ("/transactions/%s" % addr2, history_json(addr2, located)),
+        "history_address_4": ("/transactions/%s" % addr4, history_json(addr4, located)),
+        "transaction_c": ("/transaction/%s" % tx_c.id.hex(), transaction_json(tx_c, located)),
+        "transaction_f": ("/transaction/%s" % tx_f.id.hex(), transaction_json(tx_f, located)),
+        # one transaction imported with its epoch pack, one by the sync loop
+        "raw_transaction_a": (
+            "/transaction/%s/raw?format=json" % tx_a.id.hex(),
+            raw_transaction_json(tx_a),
+        ),
+        "raw_transaction_f": (
+            "/transaction/%s/raw?format=json" % tx_f.id.hex(),
+            raw_transaction_json(tx_f),
+        ),
+    }
+    for name, (path, response) in expected.items():
+        write(
+            os.path.join(FIXTURE, "expected", name + ".json"),
+            json.dumps({"path": path, "response": response}, indent=2) + "\n",
+            "w",
+        )
+
+
+if __name__ == "__main__":
+    main()